@@ -0,0 +1,90 @@
+// Shared by the admin pages under /users.
+
+// Cookie-authenticated writes must echo the `csrf_token` cookie set at login.
+function csrfHeaders() {
+  const match = document.cookie.match(/(?:^|;\s*)csrf_token=([^;]*)/);
+  return match ? { "X-CSRF-Token": decodeURIComponent(match[1]) } : {};
+}
+
+// Every API response wraps its payload as `{ code, msg, data }`; errors raised
+// before reaching a handler may carry `{ error: { brief } }` instead.
+async function apiRequest(url, options = {}) {
+  const response = await fetch(url, {
+    credentials: "same-origin",
+    ...options,
+    headers: {
+      "Content-Type": "application/json",
+      accept: "application/json",
+      ...csrfHeaders(),
+      ...(options.headers || {}),
+    },
+  });
+  if (response.status === 401) {
+    window.location.href = "/login";
+    throw new Error("Your session has ended.");
+  }
+  const body = await response.json().catch(() => ({}));
+  if (!response.ok) {
+    const fields = (body.data?.errors || []).map((e) => `${e.field}: ${e.message || e.code}`);
+    throw new Error(fields.join("; ") || body.msg || body.error?.brief || response.statusText);
+  }
+  return body.data;
+}
+
+function formatDate(value) {
+  return value ? new Date(value).toLocaleString() : "—";
+}
+
+// `datetime-local` inputs work in local time without a zone.
+function toLocalInput(value) {
+  if (!value) return "";
+  const date = new Date(value);
+  date.setMinutes(date.getMinutes() - date.getTimezoneOffset());
+  return date.toISOString().slice(0, 16);
+}
+
+function fromLocalInput(value) {
+  return value ? new Date(value).toISOString() : null;
+}
+
+// Opens the VIP dialog for `user` and resolves with the updated user, or
+// undefined when cancelled. User data only ever goes in as text or DOM
+// properties, never into the dialog's HTML.
+async function editVipDialog(user) {
+  const result = await Swal.fire({
+    titleText: `VIP for ${user.email}`,
+    showCancelButton: true,
+    confirmButtonText: "Save",
+    cancelButtonText: "Cancel",
+    html: `
+    <label class="swal2-checkbox" style="display:flex">
+      <input id="vip-is-vip" type="checkbox">
+      <span class="swal2-label">VIP</span>
+    </label>
+    <input id="vip-level" class="swal2-input" type="number" min="0" placeholder="Level">
+    <input id="vip-start" class="swal2-input" type="datetime-local">
+    <input id="vip-end" class="swal2-input" type="datetime-local">
+    `,
+    didOpen: () => {
+      document.getElementById("vip-is-vip").checked = Boolean(user.is_vip);
+      document.getElementById("vip-level").value = user.vip_level;
+      document.getElementById("vip-start").value = toLocalInput(user.vip_start_time);
+      document.getElementById("vip-end").value = toLocalInput(user.vip_end_time);
+    },
+    preConfirm: () => {
+      return apiRequest(`/api/admin/users/${user.id}/vip`, {
+        method: "PUT",
+        body: JSON.stringify({
+          is_vip: document.getElementById("vip-is-vip").checked,
+          vip_level: Number(document.getElementById("vip-level").value || 0),
+          vip_start_time: fromLocalInput(document.getElementById("vip-start").value),
+          vip_end_time: fromLocalInput(document.getElementById("vip-end").value),
+        }),
+      }).catch((error) => {
+        Swal.showValidationMessage(`Request failed: ${error.message}`);
+      });
+    },
+    allowOutsideClick: () => !Swal.isLoading(),
+  });
+  return result.isConfirmed ? result.value : undefined;
+}
@@ -2,6 +2,20 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
 mod m20240102_000001_add_vip_fields;
+mod m20261019_000001_add_login_lockout;
+mod m20261019_000002_add_totp;
+mod m20261019_000003_add_email_verification;
+mod m20261019_000004_add_user_identities;
+mod m20261019_000005_add_api_keys;
+mod m20261019_000006_add_is_admin;
+mod m20261019_000007_add_audit_events;
+mod m20261019_000008_add_sessions;
+mod m20261019_000009_add_users_deleted_at;
+mod m20261019_000010_add_data_exports;
+mod m20261019_000011_add_account_deletion;
+mod m20261019_000012_add_users_locale;
+mod m20261019_000013_add_users_password_set;
+mod m20261019_000014_add_users_totp_last_step;
 
 pub struct Migrator;
 
@@ -11,6 +25,20 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20240102_000001_add_vip_fields::Migration),
+            Box::new(m20261019_000001_add_login_lockout::Migration),
+            Box::new(m20261019_000002_add_totp::Migration),
+            Box::new(m20261019_000003_add_email_verification::Migration),
+            Box::new(m20261019_000004_add_user_identities::Migration),
+            Box::new(m20261019_000005_add_api_keys::Migration),
+            Box::new(m20261019_000006_add_is_admin::Migration),
+            Box::new(m20261019_000007_add_audit_events::Migration),
+            Box::new(m20261019_000008_add_sessions::Migration),
+            Box::new(m20261019_000009_add_users_deleted_at::Migration),
+            Box::new(m20261019_000010_add_data_exports::Migration),
+            Box::new(m20261019_000011_add_account_deletion::Migration),
+            Box::new(m20261019_000012_add_users_locale::Migration),
+            Box::new(m20261019_000013_add_users_password_set::Migration),
+            Box::new(m20261019_000014_add_users_totp_last_step::Migration),
         ]
     }
 }
@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::TotpSecret).string().null())
+                    .add_column(
+                        ColumnDef::new(Users::TotpEnabled)
+                            .boolean()
+                            .not_null()
+                            .default(false)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserRecoveryCodes::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserRecoveryCodes::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserRecoveryCodes::UserId).string().not_null())
+                    .col(ColumnDef::new(UserRecoveryCodes::CodeHash).string().not_null())
+                    .col(ColumnDef::new(UserRecoveryCodes::UsedAt).date_time().null())
+                    .col(ColumnDef::new(UserRecoveryCodes::CreatedAt).date_time().not_null())
+                    .index(
+                        Index::create()
+                            .name("idx_user_recovery_codes_user_id")
+                            .col(UserRecoveryCodes::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserRecoveryCodes::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::TotpSecret)
+                    .drop_column(Users::TotpEnabled)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    TotpSecret,
+    TotpEnabled,
+}
+
+#[derive(Iden)]
+enum UserRecoveryCodes {
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    UsedAt,
+    CreatedAt,
+}
@@ -0,0 +1,89 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::EmailVerifiedAt).date_time().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // 已有账号视为已验证，避免开启 require_verified_email 后无法登录
+        let update = Query::update()
+            .table(Users::Table)
+            .values([(Users::EmailVerifiedAt, Expr::current_timestamp().into())])
+            .to_owned();
+        manager.exec_stmt(update).await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserTokens::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserTokens::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserTokens::UserId).string().not_null())
+                    .col(ColumnDef::new(UserTokens::Purpose).string().not_null())
+                    .col(
+                        ColumnDef::new(UserTokens::TokenHash)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(UserTokens::ExpiresAt).date_time().not_null())
+                    .col(ColumnDef::new(UserTokens::UsedAt).date_time().null())
+                    .col(ColumnDef::new(UserTokens::CreatedAt).date_time().not_null())
+                    .index(
+                        Index::create()
+                            .name("idx_user_tokens_user_id_purpose")
+                            .col(UserTokens::UserId)
+                            .col(UserTokens::Purpose),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserTokens::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::EmailVerifiedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    EmailVerifiedAt,
+}
+
+#[derive(Iden)]
+enum UserTokens {
+    Table,
+    Id,
+    UserId,
+    Purpose,
+    TokenHash,
+    ExpiresAt,
+    UsedAt,
+    CreatedAt,
+}
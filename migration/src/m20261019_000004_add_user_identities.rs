@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserIdentities::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserIdentities::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserIdentities::UserId).string().not_null())
+                    .col(ColumnDef::new(UserIdentities::Provider).string().not_null())
+                    .col(ColumnDef::new(UserIdentities::Subject).string().not_null())
+                    .col(ColumnDef::new(UserIdentities::Email).string().null())
+                    .col(ColumnDef::new(UserIdentities::CreatedAt).date_time().not_null())
+                    .col(ColumnDef::new(UserIdentities::LastLoginAt).date_time().not_null())
+                    .index(
+                        Index::create()
+                            .name("idx_user_identities_provider_subject")
+                            .col(UserIdentities::Provider)
+                            .col(UserIdentities::Subject)
+                            .unique(),
+                    )
+                    .index(
+                        Index::create()
+                            .name("idx_user_identities_user_id")
+                            .col(UserIdentities::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserIdentities::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UserIdentities {
+    Table,
+    Id,
+    UserId,
+    Provider,
+    Subject,
+    Email,
+    CreatedAt,
+    LastLoginAt,
+}
@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ApiKeys::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ApiKeys::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ApiKeys::UserId).string().not_null())
+                    .col(ColumnDef::new(ApiKeys::Name).string().not_null())
+                    .col(
+                        ColumnDef::new(ApiKeys::Prefix)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(ApiKeys::SecretHash).string().not_null())
+                    .col(ColumnDef::new(ApiKeys::Scopes).string().not_null())
+                    .col(ColumnDef::new(ApiKeys::ExpiresAt).date_time().null())
+                    .col(ColumnDef::new(ApiKeys::LastUsedAt).date_time().null())
+                    .col(ColumnDef::new(ApiKeys::RevokedAt).date_time().null())
+                    .col(ColumnDef::new(ApiKeys::CreatedAt).date_time().not_null())
+                    .index(
+                        Index::create()
+                            .name("idx_api_keys_user_id")
+                            .col(ApiKeys::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ApiKeys::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum ApiKeys {
+    Table,
+    Id,
+    UserId,
+    Name,
+    Prefix,
+    SecretHash,
+    Scopes,
+    ExpiresAt,
+    LastUsedAt,
+    RevokedAt,
+    CreatedAt,
+}
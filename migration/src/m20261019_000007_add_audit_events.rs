@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuditEvents::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AuditEvents::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AuditEvents::ActorId).string().null())
+                    .col(ColumnDef::new(AuditEvents::ImpersonatorId).string().null())
+                    .col(ColumnDef::new(AuditEvents::Action).string().not_null())
+                    .col(ColumnDef::new(AuditEvents::TargetType).string().null())
+                    .col(ColumnDef::new(AuditEvents::TargetId).string().null())
+                    .col(ColumnDef::new(AuditEvents::Diff).json().null())
+                    .col(ColumnDef::new(AuditEvents::Ip).string().null())
+                    .col(ColumnDef::new(AuditEvents::RequestId).string().null())
+                    .col(ColumnDef::new(AuditEvents::CreatedAt).date_time().not_null())
+                    .index(
+                        Index::create()
+                            .name("idx_audit_events_actor_id")
+                            .col(AuditEvents::ActorId),
+                    )
+                    .index(
+                        Index::create()
+                            .name("idx_audit_events_target")
+                            .col(AuditEvents::TargetType)
+                            .col(AuditEvents::TargetId),
+                    )
+                    .index(
+                        Index::create()
+                            .name("idx_audit_events_created_at")
+                            .col(AuditEvents::CreatedAt),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuditEvents::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum AuditEvents {
+    Table,
+    Id,
+    ActorId,
+    ImpersonatorId,
+    Action,
+    TargetType,
+    TargetId,
+    Diff,
+    Ip,
+    RequestId,
+    CreatedAt,
+}
@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Sessions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Sessions::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Sessions::UserId).string().not_null())
+                    .col(ColumnDef::new(Sessions::Ip).string().null())
+                    .col(ColumnDef::new(Sessions::UserAgent).string_len(512).null())
+                    .col(ColumnDef::new(Sessions::Device).string().not_null())
+                    .col(ColumnDef::new(Sessions::CreatedAt).date_time().not_null())
+                    .col(ColumnDef::new(Sessions::LastUsedAt).date_time().not_null())
+                    .col(ColumnDef::new(Sessions::ExpiresAt).date_time().not_null())
+                    .col(ColumnDef::new(Sessions::RevokedAt).date_time().null())
+                    .index(
+                        Index::create()
+                            .name("idx_sessions_user_id")
+                            .col(Sessions::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Sessions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Sessions {
+    Table,
+    Id,
+    UserId,
+    Ip,
+    UserAgent,
+    Device,
+    CreatedAt,
+    LastUsedAt,
+    ExpiresAt,
+    RevokedAt,
+}
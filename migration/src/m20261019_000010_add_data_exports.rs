@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DataExports::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(DataExports::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(DataExports::UserId).string().not_null())
+                    .col(ColumnDef::new(DataExports::Status).string().not_null())
+                    .col(ColumnDef::new(DataExports::CreatedAt).date_time().not_null())
+                    .col(ColumnDef::new(DataExports::CompletedAt).date_time().null())
+                    .col(ColumnDef::new(DataExports::ExpiresAt).date_time().null())
+                    .index(
+                        Index::create()
+                            .name("idx_data_exports_user_id")
+                            .col(DataExports::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DataExports::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum DataExports {
+    Table,
+    Id,
+    UserId,
+    Status,
+    CreatedAt,
+    CompletedAt,
+    ExpiresAt,
+}
@@ -0,0 +1,92 @@
+use salvo::prelude::*;
+use sea_orm::{EntityTrait, Set};
+use ulid::Ulid;
+
+use crate::entities::{audit_events, prelude::AuditEvents};
+use crate::hoops::{jwt, request_id};
+use crate::{db, utils};
+
+pub const ACTION_USER_CREATE: &str = "user.create";
+pub const ACTION_USER_UPDATE: &str = "user.update";
+pub const ACTION_USER_DELETE: &str = "user.delete";
+pub const ACTION_USER_RESTORE: &str = "user.restore";
+pub const ACTION_USER_IMPORT: &str = "user.import";
+pub const ACTION_USER_ANONYMIZE: &str = "user.anonymize";
+pub const ACTION_VIP_UPDATE: &str = "user.vip.update";
+pub const ACTION_ACCOUNT_DELETION_REQUEST: &str = "account.deletion.request";
+pub const ACTION_ACCOUNT_DELETION_CANCEL: &str = "account.deletion.cancel";
+pub const ACTION_DATA_EXPORT: &str = "account.export";
+pub const ACTION_LOGIN: &str = "auth.login";
+pub const ACTION_LOGIN_FAILED: &str = "auth.login_failed";
+pub const ACTION_SESSION_REVOKE: &str = "session.revoke";
+pub const ACTION_IMPERSONATION_START: &str = "impersonation.start";
+pub const ACTION_IMPERSONATION_REQUEST: &str = "impersonation.request";
+
+/// One entry of the append-only audit log.
+#[derive(Default, Debug)]
+pub struct Event<'a> {
+    pub action: &'a str,
+    /// Set for events without an authenticated user yet, such as logins.
+    pub actor_id: Option<&'a str>,
+    pub target_type: Option<&'a str>,
+    pub target_id: Option<&'a str>,
+    /// Usually `{"before": ..., "after": ...}`.
+    pub diff: Option<serde_json::Value>,
+}
+
+/// A `{"before": ..., "after": ...}` diff; either side is `null` for creations
+/// and deletions.
+pub fn diff<T: serde::Serialize>(before: Option<&T>, after: Option<&T>) -> serde_json::Value {
+    serde_json::json!({ "before": before, "after": after })
+}
+
+/// Writes `event`, attributing it to `event.actor_id` or else the authenticated
+/// user (and the admin impersonating them, if any) with the client IP and request id.
+///
+/// A failed write is logged rather than failing the request that caused it.
+pub async fn record(req: &Request, depot: &Depot, event: Event<'_>) {
+    let claims = jwt::current_claims(depot).ok();
+    let actor_id = event
+        .actor_id
+        .map(str::to_owned)
+        .or_else(|| claims.map(|claims| claims.uid.clone()));
+    let model = audit_events::ActiveModel {
+        actor_id: Set(actor_id),
+        impersonator_id: Set(claims.and_then(|claims| claims.impersonator_id.clone())),
+        ip: Set(utils::client_ip(req)),
+        request_id: Set(request_id::request_id(depot).map(str::to_owned)),
+        ..new_model(event)
+    };
+    insert(model).await;
+}
+
+/// Writes `event` for work done outside a request, such as background jobs.
+pub async fn record_system(event: Event<'_>) {
+    let model = audit_events::ActiveModel {
+        actor_id: Set(event.actor_id.map(str::to_owned)),
+        impersonator_id: Set(None),
+        ip: Set(None),
+        request_id: Set(None),
+        ..new_model(event)
+    };
+    insert(model).await;
+}
+
+fn new_model(event: Event<'_>) -> audit_events::ActiveModel {
+    audit_events::ActiveModel {
+        id: Set(Ulid::new().to_string()),
+        action: Set(event.action.to_owned()),
+        target_type: Set(event.target_type.map(str::to_owned)),
+        target_id: Set(event.target_id.map(str::to_owned)),
+        diff: Set(event.diff),
+        created_at: Set(utils::now_primitive()),
+        ..Default::default()
+    }
+}
+
+async fn insert(model: audit_events::ActiveModel) {
+    let action = model.action.clone().unwrap();
+    if let Err(e) = AuditEvents::insert(model).exec(db::pool()).await {
+        tracing::error!(error = ?e, action, "failed to write audit event");
+    }
+}
@@ -0,0 +1,70 @@
+use cookie::{Cookie, CookieBuilder, SameSite};
+use serde::Deserialize;
+
+/// Attributes shared by every cookie we set on `/`: `jwt_token`, `csrf_token`
+/// and `locale`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CookieConfig {
+    /// Only send cookies over HTTPS. Defaults to on when `[tls]` is configured;
+    /// set it explicitly behind a TLS-terminating proxy.
+    pub secure: Option<bool>,
+    /// Valid values: strict | lax | none. `none` requires `secure`.
+    #[serde(default = "default_same_site")]
+    pub same_site: String,
+    /// Share the cookies with subdomains of this domain; host-only when unset.
+    pub domain: Option<String>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            secure: None,
+            same_site: default_same_site(),
+            domain: None,
+        }
+    }
+}
+
+impl CookieConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.same_site() {
+            None => Err(format!("{} is not strict, lax or none", self.same_site)),
+            Some(SameSite::None) if !self.is_secure() => Err("same_site = \"none\" requires secure".into()),
+            Some(_) => Ok(()),
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.secure.unwrap_or(false)
+    }
+
+    fn same_site(&self) -> Option<SameSite> {
+        match self.same_site.to_ascii_lowercase().as_str() {
+            "strict" => Some(SameSite::Strict),
+            "lax" => Some(SameSite::Lax),
+            "none" => Some(SameSite::None),
+            _ => None,
+        }
+    }
+
+    /// A cookie on `/` with the configured attributes.
+    pub fn build(&self, name: &'static str, value: impl Into<String>) -> CookieBuilder<'static> {
+        let mut builder = Cookie::build((name, value.into()))
+            .path("/")
+            .secure(self.is_secure())
+            .same_site(self.same_site().unwrap_or(SameSite::Lax));
+        if let Some(domain) = &self.domain {
+            builder = builder.domain(domain.clone());
+        }
+        builder
+    }
+
+    /// Tells the browser to drop a cookie set through [`CookieConfig::build`].
+    pub fn expired(&self, name: &'static str) -> Cookie<'static> {
+        self.build(name, "").max_age(cookie::time::Duration::ZERO).build()
+    }
+}
+
+fn default_same_site() -> String {
+    "lax".into()
+}
@@ -0,0 +1,199 @@
+use salvo::http::{HeaderName, Method};
+use serde::Deserialize;
+
+/// Cross-origin access, with one policy for `/api` and one for everything else.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct CorsConfig {
+    #[serde(default)]
+    pub api: CorsPolicyConfig,
+    #[serde(default)]
+    pub pages: CorsPolicyConfig,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CorsPolicyConfig {
+    /// Origins allowed to call us: exact (`https://app.example.com`), any
+    /// subdomain (`https://*.example.com`) or `*`. Empty means same-origin only.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Lets browsers send cookies and `Authorization`; not allowed with `*`.
+    #[serde(default)]
+    pub allow_credentials: bool,
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts on other origins may read.
+    #[serde(default = "default_exposed_headers")]
+    pub exposed_headers: Vec<String>,
+    /// How long browsers may cache a preflight, in seconds.
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+}
+
+impl Default for CorsPolicyConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allow_credentials: false,
+            allowed_methods: default_allowed_methods(),
+            allowed_headers: default_allowed_headers(),
+            exposed_headers: default_exposed_headers(),
+            max_age: default_max_age(),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.api.validate().map_err(|e| format!("cors.api: {e}"))?;
+        self.pages.validate().map_err(|e| format!("cors.pages: {e}"))
+    }
+}
+
+impl CorsPolicyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for origin in &self.allowed_origins {
+            if origin == "*" {
+                if self.allow_credentials {
+                    return Err("allowed_origins = \"*\" cannot be combined with allow_credentials".into());
+                }
+            } else if OriginPattern::parse(origin).is_none() {
+                return Err(format!("{origin} is not an origin or a *.subdomain pattern"));
+            }
+        }
+        for (name, values) in [
+            ("allowed_methods", &self.allowed_methods),
+            ("allowed_headers", &self.allowed_headers),
+            ("exposed_headers", &self.exposed_headers),
+        ] {
+            if values.iter().any(|v| v == "*") {
+                return Err(format!("{name} must list names, not \"*\""));
+            }
+        }
+        if let Some(method) = self.allowed_methods.iter().find(|m| m.parse::<Method>().is_err()) {
+            return Err(format!("{method} is not an HTTP method"));
+        }
+        let mut headers = self.allowed_headers.iter().chain(&self.exposed_headers);
+        if let Some(header) = headers.find(|h| h.parse::<HeaderName>().is_err()) {
+            return Err(format!("{header} is not a header name"));
+        }
+        Ok(())
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Whether `origin` matches an entry of `allowed_origins`.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allows_any_origin()
+            || self
+                .allowed_origins
+                .iter()
+                .filter_map(|pattern| OriginPattern::parse(pattern))
+                .any(|pattern| pattern.matches(origin))
+    }
+}
+
+/// An `allowed_origins` entry other than `*`.
+enum OriginPattern<'a> {
+    Exact(&'a str),
+    /// `https://*.example.com` as `("https://", "example.com")`.
+    Subdomain(&'a str, &'a str),
+}
+
+impl<'a> OriginPattern<'a> {
+    fn parse(pattern: &'a str) -> Option<Self> {
+        let (scheme, host) = pattern.split_once("://")?;
+        if scheme.is_empty() || host.is_empty() || host.contains('/') {
+            return None;
+        }
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains('*') => {
+                Some(Self::Subdomain(&pattern[..scheme.len() + 3], domain))
+            }
+            Some(_) => None,
+            None if host.contains('*') => None,
+            None => Some(Self::Exact(pattern)),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Exact(pattern) => pattern.eq_ignore_ascii_case(origin),
+            Self::Subdomain(scheme, domain) => {
+                let origin = origin.to_ascii_lowercase();
+                let Some(host) = origin.strip_prefix(&scheme.to_ascii_lowercase()) else {
+                    return false;
+                };
+                let Some(subdomain) = host.strip_suffix(&domain.to_ascii_lowercase()) else {
+                    return false;
+                };
+                subdomain
+                    .strip_suffix('.')
+                    .is_some_and(|labels| {
+                        !labels.is_empty()
+                            && labels.split('.').all(|label| {
+                                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                            })
+                    })
+            }
+        }
+    }
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"].map(String::from).to_vec()
+}
+fn default_allowed_headers() -> Vec<String> {
+    [
+        "authorization",
+        "content-type",
+        "accept-language",
+        "x-csrf-token",
+        "x-request-id",
+        "traceparent",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+fn default_exposed_headers() -> Vec<String> {
+    vec!["x-request-id".into()]
+}
+fn default_max_age() -> u64 {
+    600
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(origins: &[&str], allow_credentials: bool) -> CorsPolicyConfig {
+        CorsPolicyConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            allow_credentials,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_exact_and_subdomain_origins() {
+        let policy = policy(&["https://app.test", "https://*.example.com"], true);
+        assert!(policy.validate().is_ok());
+        assert!(policy.allows_origin("https://app.test"));
+        assert!(policy.allows_origin("https://a.example.com"));
+        assert!(policy.allows_origin("https://a.b.example.com"));
+        assert!(!policy.allows_origin("https://example.com"));
+        assert!(!policy.allows_origin("https://evilexample.com"));
+        assert!(!policy.allows_origin("http://a.example.com"));
+        assert!(!policy.allows_origin("https://app.test.evil"));
+    }
+
+    #[test]
+    fn rejects_wildcard_with_credentials() {
+        assert!(policy(&["*"], false).validate().is_ok());
+        assert!(policy(&["*"], true).validate().is_err());
+        assert!(policy(&["https://a.*.com"], false).validate().is_err());
+    }
+}
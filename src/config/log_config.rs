@@ -1,7 +1,12 @@
 // https://github.com/clia/tracing-config/blob/main/src/lib.rs
+use opentelemetry_sdk::trace::Tracer;
 use serde::Deserialize;
+use tracing::Subscriber;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
 
 use tracing_appender::rolling;
 
@@ -171,8 +176,10 @@ impl LogConfig {
 
     /// Init tracing log.
     ///
+    /// When a `tracer` is given, spans are also exported through OpenTelemetry.
+    ///
     /// Caller should hold the guard.
-    pub fn guard(&self) -> WorkerGuard {
+    pub fn guard(&self, tracer: Option<Tracer>) -> WorkerGuard {
         // Tracing appender init.
         let file_appender = match &*self.rolling {
             "minutely" => rolling::minutely(&self.directory, &self.file_name),
@@ -202,9 +209,9 @@ impl LogConfig {
                     .with_source_location(self.with_source_location),
             );
             if self.stdout {
-                subscriber.with_writer(std::io::stdout).init();
+                init_with_tracer(subscriber.with_writer(std::io::stdout).finish(), tracer);
             } else {
-                subscriber.with_writer(file_writer).init();
+                init_with_tracer(subscriber.with_writer(file_writer).finish(), tracer);
             };
         } else if self.format == FORMAT_COMPACT {
             let subscriber = subscriber.event_format(
@@ -217,9 +224,9 @@ impl LogConfig {
                     .with_source_location(self.with_source_location),
             );
             if self.stdout {
-                subscriber.with_writer(std::io::stdout).init();
+                init_with_tracer(subscriber.with_writer(std::io::stdout).finish(), tracer);
             } else {
-                subscriber.with_writer(file_writer).init();
+                init_with_tracer(subscriber.with_writer(file_writer).finish(), tracer);
             };
         } else if self.format == FORMAT_JSON {
             let subscriber = subscriber.event_format(
@@ -232,9 +239,9 @@ impl LogConfig {
                     .with_source_location(self.with_source_location),
             );
             if self.stdout {
-                subscriber.json().with_writer(std::io::stdout).init();
+                init_with_tracer(subscriber.json().with_writer(std::io::stdout).finish(), tracer);
             } else {
-                subscriber.json().with_writer(file_writer).init();
+                init_with_tracer(subscriber.json().with_writer(file_writer).finish(), tracer);
             };
         } else if self.format == FORMAT_FULL {
             let subscriber = subscriber.event_format(
@@ -246,9 +253,9 @@ impl LogConfig {
                     .with_source_location(self.with_source_location),
             );
             if self.stdout {
-                subscriber.with_writer(std::io::stdout).init();
+                init_with_tracer(subscriber.with_writer(std::io::stdout).finish(), tracer);
             } else {
-                subscriber.with_writer(file_writer).init();
+                init_with_tracer(subscriber.with_writer(file_writer).finish(), tracer);
             };
         }
 
@@ -256,3 +263,12 @@ impl LogConfig {
         guard
     }
 }
+
+fn init_with_tracer<S>(subscriber: S, tracer: Option<Tracer>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    subscriber
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+}
@@ -0,0 +1,83 @@
+use serde::Deserialize;
+
+use super::default_false;
+
+pub const MAIL_BACKEND_SMTP: &str = "smtp";
+pub const MAIL_BACKEND_FILE: &str = "file";
+pub const MAIL_BACKEND_STDOUT: &str = "stdout";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MailConfig {
+    /// Valid values: smtp | file | stdout
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    #[serde(default = "default_from")]
+    pub from: String,
+    /// Where the `file` backend drops one file per message.
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// Public address of this server, used to build links in emails.
+    #[serde(default = "default_link_base_url")]
+    pub link_base_url: String,
+    pub smtp: Option<SmtpConfig>,
+
+    /// Reject logins until the email address has been verified.
+    #[serde(default = "default_false")]
+    pub require_verified_email: bool,
+    #[serde(default = "default_verify_email_expiry")]
+    pub verify_email_expiry: i64,
+    #[serde(default = "default_password_reset_expiry")]
+    pub password_reset_expiry: i64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Use STARTTLS on the plain port instead of implicit TLS.
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            backend: default_backend(),
+            from: default_from(),
+            directory: default_directory(),
+            link_base_url: default_link_base_url(),
+            smtp: None,
+            require_verified_email: false,
+            verify_email_expiry: default_verify_email_expiry(),
+            password_reset_expiry: default_password_reset_expiry(),
+        }
+    }
+}
+
+fn default_backend() -> String {
+    MAIL_BACKEND_STDOUT.into()
+}
+fn default_from() -> String {
+    "TTBox <no-reply@localhost>".into()
+}
+fn default_directory() -> String {
+    "./mails".into()
+}
+fn default_link_base_url() -> String {
+    "http://127.0.0.1:8008".into()
+}
+fn default_verify_email_expiry() -> i64 {
+    24 * 3600
+}
+fn default_password_reset_expiry() -> i64 {
+    3600
+}
+fn default_smtp_port() -> u16 {
+    587
+}
+fn default_starttls() -> bool {
+    true
+}
@@ -8,6 +8,16 @@ mod log_config;
 pub use log_config::LogConfig;
 mod db_config;
 pub use db_config::DbConfig;
+mod mail_config;
+pub use mail_config::{MAIL_BACKEND_FILE, MAIL_BACKEND_SMTP, MAIL_BACKEND_STDOUT, MailConfig};
+mod oidc_config;
+pub use oidc_config::{OidcConfig, OidcProviderConfig};
+mod rate_limit_config;
+pub use rate_limit_config::{QuotaConfig, RateLimitConfig};
+mod cors_config;
+pub use cors_config::{CorsConfig, CorsPolicyConfig};
+mod cookie_config;
+pub use cookie_config::CookieConfig;
 
 pub static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
 
@@ -32,6 +42,19 @@ pub fn init() {
         eprintln!("DATABASE_URL is not set");
         std::process::exit(1);
     }
+    if let Err(e) = config.cors.validate() {
+        eprintln!("Invalid CORS config: {e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = config.oidc.validate() {
+        eprintln!("Invalid OIDC config: {e}");
+        std::process::exit(1);
+    }
+    config.cookie.secure.get_or_insert(config.tls.is_some());
+    if let Err(e) = config.cookie.validate() {
+        eprintln!("Invalid cookie config: {e}");
+        std::process::exit(1);
+    }
     crate::config::CONFIG
         .set(config)
         .expect("config should be set");
@@ -49,19 +72,322 @@ pub struct ServerConfig {
     pub log: LogConfig,
     pub jwt: JwtConfig,
     pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub mfa: MfaConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub magic_link: MagicLinkConfig,
+    #[serde(default)]
+    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub soft_delete: SoftDeleteConfig,
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub i18n: I18nConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub cookie: CookieConfig,
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct JwtConfig {
+    /// HS256 secret, used only while `keys` is empty.
     pub secret: String,
     pub expiry: i64,
+    /// Lifetime of tokens minted by admin impersonation, in seconds.
+    #[serde(default = "default_impersonation_expiry")]
+    pub impersonation_expiry: i64,
+    /// `kid` of the key in `keys` that signs new tokens.
+    pub active_kid: Option<String>,
+    /// Asymmetric keys. Keep a rotated-out key listed until the tokens it
+    /// signed have expired; it then only verifies and is published in the JWKS.
+    #[serde(default)]
+    pub keys: Vec<JwtKeyConfig>,
+}
+
+fn default_impersonation_expiry() -> i64 {
+    900
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct JwtKeyConfig {
+    pub kid: String,
+    /// Valid values: RS256 | EdDSA
+    pub algorithm: String,
+    /// PEM file; only required for the active key.
+    pub private_key_file: Option<String>,
+    /// PEM file (SPKI, or PKCS#1 for RSA).
+    pub public_key_file: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MfaConfig {
+    /// Issuer shown by authenticator apps next to the account name.
+    #[serde(default = "default_mfa_issuer")]
+    pub issuer: String,
+    /// Lifetime in seconds of the token handed out between password and code.
+    #[serde(default = "default_mfa_pending_expiry")]
+    pub pending_expiry: i64,
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        Self {
+            issuer: default_mfa_issuer(),
+            pending_expiry: default_mfa_pending_expiry(),
+        }
+    }
+}
+
+fn default_mfa_issuer() -> String {
+    "TTBox".into()
+}
+fn default_mfa_pending_expiry() -> i64 {
+    300
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MagicLinkConfig {
+    /// Lifetime of an emailed login link, in seconds.
+    #[serde(default = "default_magic_link_expiry")]
+    pub expiry: i64,
+    /// Where to go after login when the client didn't ask for anything else.
+    #[serde(default = "default_magic_link_redirect")]
+    pub default_redirect: String,
+    /// Redirect targets clients may ask for. An entry matches exactly, or as a
+    /// prefix when it ends with `/`.
+    #[serde(default = "default_magic_link_allowed_redirects")]
+    pub allowed_redirects: Vec<String>,
+}
+
+impl Default for MagicLinkConfig {
+    fn default() -> Self {
+        Self {
+            expiry: default_magic_link_expiry(),
+            default_redirect: default_magic_link_redirect(),
+            allowed_redirects: default_magic_link_allowed_redirects(),
+        }
+    }
+}
+
+impl MagicLinkConfig {
+    pub fn is_allowed_redirect(&self, redirect: &str) -> bool {
+        redirect == self.default_redirect
+            || self.allowed_redirects.iter().any(|allowed| {
+                redirect == allowed || (allowed.ends_with('/') && redirect.starts_with(allowed.as_str()))
+            })
+    }
+}
+
+fn default_magic_link_expiry() -> i64 {
+    900
+}
+fn default_magic_link_redirect() -> String {
+    "/users".into()
+}
+fn default_magic_link_allowed_redirects() -> Vec<String> {
+    vec!["/users".into()]
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SoftDeleteConfig {
+    /// Days a deleted user can still be restored before it is purged.
+    #[serde(default = "default_retention_days")]
+    pub retention_days: i64,
+    /// Seconds between purge runs.
+    #[serde(default = "default_purge_interval")]
+    pub purge_interval: u64,
+}
+
+impl Default for SoftDeleteConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: default_retention_days(),
+            purge_interval: default_purge_interval(),
+        }
+    }
+}
+
+fn default_retention_days() -> i64 {
+    30
+}
+fn default_purge_interval() -> u64 {
+    3600
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PrivacyConfig {
+    /// Where finished data exports are written.
+    #[serde(default = "default_export_directory")]
+    pub export_directory: String,
+    /// Seconds a finished export can be downloaded before it is removed.
+    #[serde(default = "default_export_expiry")]
+    pub export_expiry: i64,
+    /// Seconds an export may stay pending before it is given up as failed,
+    /// e.g. because the server restarted while building it.
+    #[serde(default = "default_export_timeout")]
+    pub export_timeout: i64,
+    /// Days between a self-deletion request and the account being anonymised.
+    #[serde(default = "default_deletion_cooling_off_days")]
+    pub deletion_cooling_off_days: i64,
+    /// Seconds after signing in during which accounts without a password,
+    /// created by a social login, may request their deletion.
+    #[serde(default = "default_reauth_window")]
+    pub reauth_window: i64,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            export_directory: default_export_directory(),
+            export_expiry: default_export_expiry(),
+            export_timeout: default_export_timeout(),
+            deletion_cooling_off_days: default_deletion_cooling_off_days(),
+            reauth_window: default_reauth_window(),
+        }
+    }
+}
+
+impl PrivacyConfig {
+    pub fn export_path(&self, export_id: &str) -> std::path::PathBuf {
+        std::path::Path::new(&self.export_directory).join(format!("{export_id}.json"))
+    }
+}
+
+fn default_export_directory() -> String {
+    "exports".into()
+}
+fn default_export_expiry() -> i64 {
+    86400
+}
+fn default_export_timeout() -> i64 {
+    3600
+}
+fn default_deletion_cooling_off_days() -> i64 {
+    14
+}
+fn default_reauth_window() -> i64 {
+    300
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StatsConfig {
+    /// Seconds the admin statistics are reused before being recomputed.
+    #[serde(default = "default_stats_cache_ttl")]
+    pub cache_ttl: u64,
+    /// Window, in days, for upcoming VIP expirations and churn when the
+    /// request doesn't give one.
+    #[serde(default = "default_stats_window_days")]
+    pub window_days: u32,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl: default_stats_cache_ttl(),
+            window_days: default_stats_window_days(),
+        }
+    }
+}
+
+fn default_stats_cache_ttl() -> u64 {
+    300
+}
+fn default_stats_window_days() -> u32 {
+    7
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct I18nConfig {
+    /// Locale for requests that name no supported one: `en` or `zh-CN`.
+    #[serde(default = "default_locale")]
+    pub default_locale: String,
 }
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        Self {
+            default_locale: default_locale(),
+        }
+    }
+}
+
+fn default_locale() -> String {
+    "en".into()
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct TlsConfig {
     pub cert: String,
     pub key: String,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+pub struct MetricsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Serve `/metrics` on its own address instead of the main listener.
+    pub listen_addr: Option<String>,
+    /// Require `Authorization: Bearer <token>` when scraping.
+    pub bearer_token: Option<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen_addr: None,
+            bearer_token: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TelemetryConfig {
+    /// Export spans over OTLP/HTTP. Local fmt logging is unaffected.
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+    #[serde(default = "default_otlp_endpoint")]
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Fraction of root traces to sample, between 0.0 and 1.0.
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            otlp_endpoint: default_otlp_endpoint(),
+            service_name: default_service_name(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://127.0.0.1:4318/v1/traces".into()
+}
+fn default_service_name() -> String {
+    "ttbox_salvo".into()
+}
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
 #[allow(dead_code)]
 pub fn default_false() -> bool {
     false
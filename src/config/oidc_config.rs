@@ -0,0 +1,135 @@
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OidcConfig {
+    /// Public address of this server; callbacks go to
+    /// `{callback_base_url}/api/oauth/{provider}/callback`.
+    #[serde(default = "default_callback_base_url")]
+    pub callback_base_url: String,
+    /// Where the browser lands after a successful social login.
+    #[serde(default = "default_redirect_after_login")]
+    pub redirect_after_login: String,
+    /// Lifetime of the signed state cookie that carries state, nonce and PKCE verifier.
+    #[serde(default = "default_state_expiry")]
+    pub state_expiry: i64,
+    #[serde(default)]
+    pub providers: Vec<OidcProviderConfig>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OidcProviderConfig {
+    /// Used in the route, e.g. `google` for `/api/oauth/google/authorize`.
+    pub name: String,
+    /// Must equal the `iss` claim of the ID tokens the provider issues.
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    /// Must be `https://`, or `http://` on a loopback address for local testing.
+    pub token_endpoint: String,
+    /// Queried when the ID token carries no email claim.
+    pub userinfo_endpoint: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            callback_base_url: default_callback_base_url(),
+            redirect_after_login: default_redirect_after_login(),
+            state_expiry: default_state_expiry(),
+            providers: Vec::new(),
+        }
+    }
+}
+
+impl OidcConfig {
+    pub fn provider(&self, name: &str) -> Option<&OidcProviderConfig> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+
+    pub fn redirect_uri(&self, provider: &str) -> String {
+        format!("{}/api/oauth/{}/callback", self.callback_base_url, provider)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for provider in &self.providers {
+            provider
+                .validate()
+                .map_err(|e| format!("oidc.providers.{}: {e}", provider.name))?;
+        }
+        Ok(())
+    }
+}
+
+impl OidcProviderConfig {
+    /// ID tokens are trusted without checking their signature because they come
+    /// straight from the token endpoint, so that has to be reached over TLS. The
+    /// userinfo endpoint supplies the email just as directly.
+    pub fn validate(&self) -> Result<(), String> {
+        require_tls("token_endpoint", &self.token_endpoint)?;
+        if let Some(userinfo_endpoint) = &self.userinfo_endpoint {
+            require_tls("userinfo_endpoint", userinfo_endpoint)?;
+        }
+        Ok(())
+    }
+}
+
+fn require_tls(name: &str, url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("{name} {url} is not a URL: {e}"))?;
+    let loopback = parsed.host_str().is_some_and(|host| {
+        host == "localhost"
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    });
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(format!("{name} {url} must use https")),
+    }
+}
+
+fn default_callback_base_url() -> String {
+    "http://127.0.0.1:8008".into()
+}
+fn default_redirect_after_login() -> String {
+    "/users".into()
+}
+fn default_state_expiry() -> i64 {
+    600
+}
+fn default_scopes() -> Vec<String> {
+    vec!["openid".into(), "email".into(), "profile".into()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_endpoint_needs_tls_unless_loopback() {
+        let provider = |token_endpoint: &str| OidcProviderConfig {
+            name: "test".into(),
+            issuer: "https://idp.test".into(),
+            authorization_endpoint: "https://idp.test/authorize".into(),
+            token_endpoint: token_endpoint.into(),
+            userinfo_endpoint: None,
+            client_id: "client".into(),
+            client_secret: "secret".into(),
+            scopes: default_scopes(),
+        };
+        assert!(provider("https://idp.test/token").validate().is_ok());
+        assert!(provider("http://127.0.0.1:9000/token").validate().is_ok());
+        assert!(provider("http://[::1]:9000/token").validate().is_ok());
+        assert!(provider("http://localhost/token").validate().is_ok());
+        assert!(provider("http://idp.test/token").validate().is_err());
+        assert!(provider("http://127.0.0.1.evil.test/token").validate().is_err());
+        assert!(provider("idp.test/token").validate().is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RateLimitConfig {
+    /// Login attempts allowed from one IP address.
+    #[serde(default = "default_login_per_ip")]
+    pub login_per_ip: QuotaConfig,
+    /// Login attempts allowed against one email address, from anywhere.
+    #[serde(default = "default_login_per_account")]
+    pub login_per_account: QuotaConfig,
+    /// Sign-ups allowed from one IP address.
+    #[serde(default = "default_register_per_ip")]
+    pub register_per_ip: QuotaConfig,
+    /// Sign-up attempts for one email address, from anywhere.
+    #[serde(default = "default_register_per_account")]
+    pub register_per_account: QuotaConfig,
+    /// Verification and password reset emails requested from one IP address.
+    #[serde(default = "default_account_email_per_ip")]
+    pub account_email_per_ip: QuotaConfig,
+    /// Password reset emails requested for one email address, from anywhere.
+    #[serde(default = "default_account_email_per_account")]
+    pub account_email_per_account: QuotaConfig,
+    /// Magic login links requested from one IP address.
+    #[serde(default = "default_magic_link_per_ip")]
+    pub magic_link_per_ip: QuotaConfig,
+    /// Magic login links requested for one email address.
+    #[serde(default = "default_magic_link_per_account")]
+    pub magic_link_per_account: QuotaConfig,
+
+    /// Consecutive wrong passwords before the account is locked.
+    #[serde(default = "default_max_failed_logins")]
+    pub max_failed_logins: i32,
+    /// How long a locked account stays locked, in seconds.
+    #[serde(default = "default_lockout_seconds")]
+    pub lockout_seconds: i64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct QuotaConfig {
+    pub limit: usize,
+    pub period_seconds: i64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            login_per_ip: default_login_per_ip(),
+            login_per_account: default_login_per_account(),
+            register_per_ip: default_register_per_ip(),
+            register_per_account: default_register_per_account(),
+            account_email_per_ip: default_account_email_per_ip(),
+            account_email_per_account: default_account_email_per_account(),
+            magic_link_per_ip: default_magic_link_per_ip(),
+            magic_link_per_account: default_magic_link_per_account(),
+            max_failed_logins: default_max_failed_logins(),
+            lockout_seconds: default_lockout_seconds(),
+        }
+    }
+}
+
+fn default_login_per_ip() -> QuotaConfig {
+    QuotaConfig {
+        limit: 20,
+        period_seconds: 60,
+    }
+}
+fn default_login_per_account() -> QuotaConfig {
+    QuotaConfig {
+        limit: 10,
+        period_seconds: 300,
+    }
+}
+fn default_register_per_ip() -> QuotaConfig {
+    QuotaConfig {
+        limit: 10,
+        period_seconds: 3600,
+    }
+}
+fn default_register_per_account() -> QuotaConfig {
+    QuotaConfig {
+        limit: 3,
+        period_seconds: 3600,
+    }
+}
+fn default_account_email_per_ip() -> QuotaConfig {
+    QuotaConfig {
+        limit: 5,
+        period_seconds: 3600,
+    }
+}
+fn default_account_email_per_account() -> QuotaConfig {
+    QuotaConfig {
+        limit: 3,
+        period_seconds: 3600,
+    }
+}
+fn default_magic_link_per_ip() -> QuotaConfig {
+    QuotaConfig {
+        limit: 10,
+        period_seconds: 3600,
+    }
+}
+fn default_magic_link_per_account() -> QuotaConfig {
+    QuotaConfig {
+        limit: 3,
+        period_seconds: 900,
+    }
+}
+fn default_max_failed_logins() -> i32 {
+    5
+}
+fn default_lockout_seconds() -> i64 {
+    900
+}
@@ -16,9 +16,10 @@ pub async fn init(config: &DbConfig) {
         .idle_timeout(Duration::from_secs(config.idle_timeout as u64))
         .sqlx_logging(config.sqlx_logging);
 
-    let pool = Database::connect(opt)
+    let mut pool = Database::connect(opt)
         .await
         .expect("db connection should connect");
+    pool.set_metric_callback(crate::telemetry::record_db_query);
     SEAORM_POOL.set(pool).expect("seaorm pool should be set");
 }
 
@@ -2,4 +2,11 @@
 
 pub mod prelude;
 
+pub mod api_keys;
+pub mod audit_events;
+pub mod data_exports;
+pub mod sessions;
+pub mod user_identities;
+pub mod user_recovery_codes;
+pub mod user_tokens;
 pub mod users;
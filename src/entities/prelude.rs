@@ -1,3 +1,10 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.3
 
+pub use super::api_keys::Entity as ApiKeys;
+pub use super::audit_events::Entity as AuditEvents;
+pub use super::data_exports::Entity as DataExports;
+pub use super::sessions::Entity as Sessions;
+pub use super::user_identities::Entity as UserIdentities;
+pub use super::user_recovery_codes::Entity as UserRecoveryCodes;
+pub use super::user_tokens::Entity as UserTokens;
 pub use super::users::Entity as Users;
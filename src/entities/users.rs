@@ -19,9 +19,40 @@ pub struct Model {
     pub vip_level: i32,
     pub created_at: time::PrimitiveDateTime,
     pub updated_at: time::PrimitiveDateTime,
+    #[sea_orm(default_value = 0)]
+    pub failed_login_count: i32,
+    pub locked_until: Option<time::PrimitiveDateTime>,
+    pub totp_secret: Option<String>,
+    #[sea_orm(default_value = false)]
+    pub totp_enabled: bool,
+    pub email_verified_at: Option<time::PrimitiveDateTime>,
+    #[sea_orm(default_value = false)]
+    pub is_admin: bool,
+    pub deleted_at: Option<time::PrimitiveDateTime>,
+    pub deletion_scheduled_at: Option<time::PrimitiveDateTime>,
+    pub anonymized_at: Option<time::PrimitiveDateTime>,
+    pub locale: Option<String>,
+    /// False for accounts created by a social login, whose `password` is a
+    /// random hash nobody knows.
+    #[sea_orm(default_value = true)]
+    pub password_set: bool,
+    /// Time step of the last accepted TOTP code; codes up to it are spent.
+    pub totp_last_step: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
+
+impl Entity {
+    /// Like `find`, without soft-deleted users.
+    pub fn find_active() -> Select<Entity> {
+        Self::find().filter(Column::DeletedAt.is_null())
+    }
+
+    /// Like `find_by_id`, without soft-deleted users.
+    pub fn find_active_by_id(id: impl Into<String>) -> Select<Entity> {
+        Self::find_by_id(id.into()).filter(Column::DeletedAt.is_null())
+    }
+}
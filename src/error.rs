@@ -1,20 +1,215 @@
+use std::collections::BTreeMap;
+
 use salvo::http::{ParseError, StatusCode, StatusError};
 use salvo::oapi::{self, EndpointOutRegister, ToSchema};
 use salvo::prelude::*;
+use sea_orm::SqlErr;
 use serde::Serialize;
 use thiserror::Error;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::i18n::{self, Locale, Message};
 
 #[derive(Serialize, ToSchema, Debug)]
 pub struct ErrorResponse {
+    /// One of the business codes of [`ErrorCode`], or the HTTP status otherwise.
     pub code: i32,
     pub msg: String,
+    /// Which fields failed validation; `null` for every other error.
+    #[salvo(schema(value_type = Option<ValidationErrorData>))]
     pub data: serde_json::Value,
+    /// Echoes the `X-Request-Id` response header so errors can be matched to log lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Trace id of the failed request when tracing export is enabled, for support tickets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+/// `data` of a validation error response.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ValidationErrorData {
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct FieldError {
+    /// Path of the field in the request, e.g. `email` or `rows[2].email`.
+    pub field: String,
+    /// The failed rule, e.g. `email`, `length` or `range`.
+    pub code: String,
+    pub message: Option<String>,
+    /// Arguments of the rule, such as `min` and `max`.
+    pub params: BTreeMap<String, serde_json::Value>,
+}
+
+impl ValidationErrorData {
+    pub fn new(errors: &ValidationErrors, locale: Locale) -> Self {
+        let mut out = Vec::new();
+        collect_field_errors("", errors, locale, &mut out);
+        out.sort_by(|a, b| a.field.cmp(&b.field));
+        Self { errors: out }
+    }
+}
+
+fn collect_field_errors(
+    prefix: &str,
+    errors: &ValidationErrors,
+    locale: Locale,
+    out: &mut Vec<FieldError>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() { field.to_string() } else { format!("{prefix}.{field}") };
+        match kind {
+            ValidationErrorsKind::Field(errors) => out.extend(errors.iter().map(|error| FieldError {
+                field: path.clone(),
+                code: error.code.to_string(),
+                message: Some(
+                    i18n::translate(locale, error.message.as_deref().unwrap_or("Invalid value."))
+                        .into_owned(),
+                ),
+                // `value` echoes the input back, which may be a password.
+                params: error
+                    .params
+                    .iter()
+                    .filter(|(name, _)| *name != "value")
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect(),
+            })),
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(&path, errors, locale, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(&format!("{path}[{index}]"), errors, locale, out);
+                }
+            }
+        }
+    }
+}
+
+/// Stable `code` values of error responses that clients can branch on.
+///
+/// Errors without one of these codes use their HTTP status as `code`. Business
+/// codes are grouped by domain: `1xxxx` accounts, `2xxxx` VIP, `3xxxx` access
+/// control. Never renumber a released code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    UserNotFound,
+    EmailTaken,
+    InvalidCredentials,
+    EmailNotVerified,
+    /// An emailed link or one-time token is invalid, used or expired.
+    InvalidLink,
+    /// A two-factor code didn't match.
+    IncorrectCode,
+    VipExpired,
+    InvalidVipWindow,
+    ScopeMissing,
+    ImpersonationForbidden,
+    /// A cookie-authenticated request didn't echo the `csrf_token` cookie.
+    CsrfTokenInvalid,
+}
+
+impl ErrorCode {
+    pub const ALL: &[ErrorCode] = &[
+        Self::UserNotFound,
+        Self::EmailTaken,
+        Self::InvalidCredentials,
+        Self::EmailNotVerified,
+        Self::InvalidLink,
+        Self::IncorrectCode,
+        Self::VipExpired,
+        Self::InvalidVipWindow,
+        Self::ScopeMissing,
+        Self::ImpersonationForbidden,
+        Self::CsrfTokenInvalid,
+    ];
+
+    pub fn code(self) -> i32 {
+        match self {
+            Self::UserNotFound => 10001,
+            Self::EmailTaken => 10002,
+            Self::InvalidCredentials => 10003,
+            Self::EmailNotVerified => 10004,
+            Self::InvalidLink => 10005,
+            Self::IncorrectCode => 10006,
+            Self::VipExpired => 20001,
+            Self::InvalidVipWindow => 20002,
+            Self::ScopeMissing => 30001,
+            Self::ImpersonationForbidden => 30002,
+            Self::CsrfTokenInvalid => 30003,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::UserNotFound => "USER_NOT_FOUND",
+            Self::EmailTaken => "EMAIL_TAKEN",
+            Self::InvalidCredentials => "INVALID_CREDENTIALS",
+            Self::EmailNotVerified => "EMAIL_NOT_VERIFIED",
+            Self::InvalidLink => "INVALID_LINK",
+            Self::IncorrectCode => "INCORRECT_CODE",
+            Self::VipExpired => "VIP_EXPIRED",
+            Self::InvalidVipWindow => "INVALID_VIP_WINDOW",
+            Self::ScopeMissing => "SCOPE_MISSING",
+            Self::ImpersonationForbidden => "IMPERSONATION_FORBIDDEN",
+            Self::CsrfTokenInvalid => "CSRF_TOKEN_INVALID",
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::UserNotFound => StatusCode::NOT_FOUND,
+            Self::EmailTaken => StatusCode::CONFLICT,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::EmailNotVerified
+            | Self::ScopeMissing
+            | Self::ImpersonationForbidden
+            | Self::CsrfTokenInvalid => StatusCode::FORBIDDEN,
+            Self::InvalidLink | Self::IncorrectCode | Self::VipExpired | Self::InvalidVipWindow => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    /// Message used when the error site doesn't give a more specific one; also
+    /// the key of its translations.
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::UserNotFound => "User does not exist.",
+            Self::EmailTaken => "This email address is already registered.",
+            Self::InvalidCredentials => "Account not exist or password is incorrect.",
+            Self::EmailNotVerified => "Please verify your email address before signing in.",
+            Self::InvalidLink => "The link is invalid or has expired.",
+            Self::IncorrectCode => "The code is incorrect.",
+            Self::VipExpired => "The VIP membership has already ended.",
+            Self::InvalidVipWindow => "vip_end_time must be after vip_start_time.",
+            Self::ScopeMissing => "This token lacks a required scope.",
+            Self::ImpersonationForbidden => "Not allowed while impersonating a user.",
+            Self::CsrfTokenInvalid => "The CSRF token is missing or invalid, please reload the page.",
+        }
+    }
+
+    /// The catalog as a markdown table, for the OpenAPI description.
+    pub fn catalog() -> String {
+        let mut table = String::from("| code | name | status |\n|---|---|---|\n");
+        for code in Self::ALL {
+            table.push_str(&format!(
+                "| {} | {} | {} |\n",
+                code.code(),
+                code.name(),
+                code.status().as_u16()
+            ));
+        }
+        table
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum AppError {
+    #[error("business error {code}: `{1}`", code = .0.name())]
+    Business(ErrorCode, Message),
     #[error("public: `{0}`")]
-    Public(String),
+    Public(Message),
     #[error("internal: `{0}`")]
     Internal(String),
     #[error("salvo internal error: `{0}`")]
@@ -31,60 +226,156 @@ pub enum AppError {
     Validation(#[from] validator::ValidationErrors),
 }
 impl AppError {
-    pub fn public<S: Into<String>>(msg: S) -> Self {
+    pub fn public<S: Into<Message>>(msg: S) -> Self {
         Self::Public(msg.into())
     }
 
     pub fn internal<S: Into<String>>(msg: S) -> Self {
         Self::Internal(msg.into())
     }
+
+    /// A catalogued error with a message more specific than [`ErrorCode::message`].
+    pub fn business<S: Into<Message>>(code: ErrorCode, msg: S) -> Self {
+        Self::Business(code, msg.into())
+    }
+}
+
+impl From<ErrorCode> for AppError {
+    fn from(code: ErrorCode) -> Self {
+        Self::Business(code, code.message().into())
+    }
 }
 
 #[async_trait]
 impl Writer for AppError {
-    async fn write(mut self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+    async fn write(mut self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
+        let locale = Locale::of(req);
+        let data = match &self {
+            Self::Validation(e) => {
+                serde_json::to_value(ValidationErrorData::new(e, locale)).unwrap_or_default()
+            }
+            _ => serde_json::Value::Null,
+        };
         let (status_code, error_code, msg) = match &self {
+            Self::Business(code, msg) => (code.status(), code.code(), msg.render(locale)),
             Self::HttpStatus(e) => {
-                let brief = if e.brief.is_empty() { e.name.clone() } else { e.brief.clone() };
-                (e.code, e.code.as_u16() as i32, brief)
+                let brief = if e.brief.is_empty() { &e.name } else { &e.brief };
+                (e.code, e.code.as_u16() as i32, i18n::translate(locale, brief).into_owned())
             }
-            Self::Public(msg) => (StatusCode::BAD_REQUEST, 400, msg.clone()),
+            Self::Public(msg) => (StatusCode::BAD_REQUEST, 400, msg.render(locale)),
             Self::Internal(msg) => {
                 tracing::error!(msg = msg, "internal error");
-                (StatusCode::INTERNAL_SERVER_ERROR, 500, "Internal server error".to_string())
+                let msg = i18n::translate(locale, "Internal server error").into_owned();
+                (StatusCode::INTERNAL_SERVER_ERROR, 500, msg)
             }
             Self::Salvo(e) => {
                 tracing::error!(error = ?e, "salvo error");
-                (StatusCode::INTERNAL_SERVER_ERROR, 500, "Unknown error happened in salvo.".to_string())
+                let msg = i18n::translate(locale, "Internal server error").into_owned();
+                (StatusCode::INTERNAL_SERVER_ERROR, 500, msg)
+            }
+            Self::Validation(_) => {
+                (StatusCode::BAD_REQUEST, 400, i18n::translate(locale, "Validation failed.").into_owned())
+            }
+            Self::HttpParse(e) => {
+                let msg = Message::new("Invalid request: {detail}").arg("detail", e);
+                (StatusCode::BAD_REQUEST, 400, msg.render(locale))
+            }
+            // `users.email` is the only unique column a client can collide with.
+            Self::Seaorm(e) => match e.sql_err() {
+                Some(SqlErr::UniqueConstraintViolation(detail)) if detail.contains("email") => {
+                    let code = ErrorCode::EmailTaken;
+                    (code.status(), code.code(), i18n::translate(locale, code.message()).into_owned())
+                }
+                Some(SqlErr::UniqueConstraintViolation(_)) => {
+                    let msg = i18n::translate(locale, "The record already exists.").into_owned();
+                    (StatusCode::CONFLICT, 409, msg)
+                }
+                _ => {
+                    tracing::error!(error = ?e, "database error");
+                    let msg = i18n::translate(locale, "Internal server error").into_owned();
+                    (StatusCode::INTERNAL_SERVER_ERROR, 500, msg)
+                }
+            },
+            e => {
+                tracing::error!(error = ?e, "unexpected error");
+                let msg = i18n::translate(locale, "Internal server error").into_owned();
+                (StatusCode::INTERNAL_SERVER_ERROR, 500, msg)
             }
-            Self::Validation(e) => (StatusCode::BAD_REQUEST, 400, format!("Validation error: {}", e)),
-            e => (StatusCode::INTERNAL_SERVER_ERROR, 500, format!("Unknown error happened: {e}")),
         };
         
         res.status_code(status_code);
         res.render(Json(ErrorResponse {
             code: error_code,
             msg,
-            data: serde_json::Value::Null,
+            data,
+            request_id: crate::hoops::request_id::request_id(depot).map(str::to_owned),
+            trace_id: crate::telemetry::current_trace_id(),
         }));
     }
 }
 impl EndpointOutRegister for AppError {
     fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
-        operation.responses.insert(
-            StatusCode::INTERNAL_SERVER_ERROR.as_str(),
-            oapi::Response::new("Internal server error")
-                .add_content("application/json", StatusError::to_schema(components)),
-        );
-        operation.responses.insert(
-            StatusCode::NOT_FOUND.as_str(),
-            oapi::Response::new("Not found")
-                .add_content("application/json", StatusError::to_schema(components)),
-        );
-        operation.responses.insert(
-            StatusCode::BAD_REQUEST.as_str(),
-            oapi::Response::new("Bad request")
-                .add_content("application/json", StatusError::to_schema(components)),
-        );
+        let schema = ErrorResponse::to_schema(components);
+        for (status, description) in [
+            (StatusCode::BAD_REQUEST, "Bad request"),
+            (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            (StatusCode::FORBIDDEN, "Forbidden"),
+            (StatusCode::NOT_FOUND, "Not found"),
+            (StatusCode::CONFLICT, "Conflict"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        ] {
+            let codes = ErrorCode::ALL
+                .iter()
+                .filter(|code| code.status() == status)
+                .map(|code| format!("`{}` {}", code.code(), code.name()))
+                .collect::<Vec<_>>();
+            let description = if codes.is_empty() {
+                description.to_owned()
+            } else {
+                format!("{description}; `code` is the HTTP status or one of {}", codes.join(", "))
+            };
+            operation.responses.insert(
+                status.as_str(),
+                oapi::Response::new(description).add_content("application/json", schema.clone()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes_are_unique() {
+        let mut codes: Vec<i32> = ErrorCode::ALL.iter().map(|code| code.code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+        assert!(codes.iter().all(|code| *code >= 10000));
+    }
+
+    #[test]
+    fn validation_errors_list_fields() {
+        use validator::Validate;
+
+        #[derive(Validate)]
+        struct Input {
+            #[validate(email(message = "Please enter a valid email address"))]
+            email: String,
+            #[validate(length(min = 6))]
+            password: String,
+        }
+        let errors = Input { email: "nope".into(), password: "123".into() }
+            .validate()
+            .unwrap_err();
+        let data = ValidationErrorData::new(&errors, Locale::ZhCn);
+        assert_eq!(data.errors.len(), 2);
+        assert_eq!(data.errors[0].field, "email");
+        assert_eq!(data.errors[0].message.as_deref(), Some("请输入有效的邮箱地址"));
+        assert_eq!(data.errors[1].field, "password");
+        assert_eq!(data.errors[1].code, "length");
+        assert_eq!(data.errors[1].params.get("min"), Some(&serde_json::json!(6)));
+        assert!(!data.errors[1].params.contains_key("value"));
     }
 }
@@ -0,0 +1,21 @@
+use salvo::prelude::*;
+
+use super::jwt;
+use crate::entities::prelude::Users;
+use crate::{db, AppResult};
+
+/// Guards the admin pages. Mount it after `page_auth_hoop`: visitors without a
+/// session cookie are sent to `/login`, signed-in users who aren't admins get 403.
+#[handler]
+pub async fn admin_page_hoop(depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) -> AppResult<()> {
+    let Ok(claims) = jwt::current_claims(depot) else {
+        res.render(Redirect::other("/login"));
+        ctrl.skip_rest();
+        return Ok(());
+    };
+    let user = Users::find_active_by_id(claims.user_id()).one(db::pool()).await?;
+    if !user.is_some_and(|user| user.is_admin) {
+        return Err(StatusError::forbidden().brief("Admin access required.").into());
+    }
+    Ok(())
+}
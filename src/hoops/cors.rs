@@ -1,9 +1,75 @@
-use salvo::cors::{AllowHeaders, AllowMethods, AllowOrigin, Cors, CorsHandler};
+use salvo::async_trait;
+use salvo::cors::{AllowOrigin, Cors, CorsHandler};
+use salvo::http::{HeaderName, Method};
+use salvo::prelude::*;
 
-pub fn cors_hoop() -> CorsHandler {
+use crate::config::{CorsConfig, CorsPolicyConfig};
+
+/// Answers CORS with the `cors.api` policy under `/api` and `cors.pages`
+/// everywhere else.
+///
+/// Preflight `OPTIONS` requests match no route, so this goes on the `Service`
+/// and picks the policy by path rather than being attached to the routers.
+pub struct CorsHoop {
+    api: CorsHandler,
+    pages: CorsHandler,
+}
+
+pub fn cors_hoop(config: &CorsConfig) -> CorsHoop {
+    CorsHoop {
+        api: policy_handler(&config.api),
+        pages: policy_handler(&config.pages),
+    }
+}
+
+fn policy_handler(policy: &CorsPolicyConfig) -> CorsHandler {
+    let allow_origin = if policy.allows_any_origin() {
+        AllowOrigin::any()
+    } else {
+        let policy = policy.clone();
+        AllowOrigin::dynamic(move |origin, _, _| {
+            origin
+                .filter(|origin| origin.to_str().is_ok_and(|origin| policy.allows_origin(origin)))
+                .cloned()
+        })
+    };
     Cors::new()
-        .allow_origin(AllowOrigin::any())
-        .allow_methods(AllowMethods::any())
-        .allow_headers(AllowHeaders::any())
+        .allow_origin(allow_origin)
+        .allow_credentials(policy.allow_credentials)
+        .allow_methods(
+            policy
+                .allowed_methods
+                .iter()
+                .filter_map(|method| method.parse().ok())
+                .collect::<Vec<Method>>(),
+        )
+        .allow_headers(
+            policy
+                .allowed_headers
+                .iter()
+                .filter_map(|header| header.parse().ok())
+                .collect::<Vec<HeaderName>>(),
+        )
+        .expose_headers(
+            policy
+                .exposed_headers
+                .iter()
+                .filter_map(|header| header.parse().ok())
+                .collect::<Vec<HeaderName>>(),
+        )
+        .max_age(policy.max_age)
         .into_handler()
 }
+
+#[async_trait]
+impl Handler for CorsHoop {
+    async fn handle(&self, req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+        let path = req.uri().path();
+        let handler = if path == "/api" || path.starts_with("/api/") {
+            &self.api
+        } else {
+            &self.pages
+        };
+        handler.handle(req, depot, res, ctrl).await;
+    }
+}
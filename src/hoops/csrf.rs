@@ -0,0 +1,48 @@
+use cookie::Cookie;
+use salvo::http::header::AUTHORIZATION;
+use salvo::jwt_auth::JwtAuthDepotExt;
+use salvo::prelude::*;
+
+use super::jwt::TOKEN_COOKIE;
+use crate::error::ErrorCode;
+use crate::{config, utils, AppResult};
+
+/// Readable by our scripts, which echo it in [`CSRF_HEADER`].
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// A fresh double-submit token, issued next to `jwt_token` and living as long.
+pub fn csrf_cookie(max_age: cookie::time::Duration) -> Cookie<'static> {
+    config::get()
+        .cookie
+        .build(CSRF_COOKIE, utils::random_string(32))
+        .max_age(max_age)
+        .build()
+}
+
+/// Requires `X-CSRF-Token` to match the `csrf_token` cookie on unsafe methods
+/// when the request was authenticated by the `jwt_token` cookie. Tokens sent
+/// in a header or the query and API keys can't be attached by another site, so
+/// those requests pass. Mount it after `auth_hoop`.
+#[handler]
+pub async fn csrf_hoop(req: &mut Request, depot: &mut Depot) -> AppResult<()> {
+    if req.method().is_safe() || !authenticated_by_cookie(req, depot) {
+        return Ok(());
+    }
+    let expected = req.cookie(CSRF_COOKIE).map(|cookie| cookie.value());
+    let sent = req.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (expected, sent) {
+        (Some(expected), Some(sent)) if !expected.is_empty() && utils::constant_time_eq(expected, sent) => Ok(()),
+        _ => Err(ErrorCode::CsrfTokenInvalid.into()),
+    }
+}
+
+/// Whether `auth_hoop` took the token from the cookie, the last place it looks.
+fn authenticated_by_cookie(req: &Request, depot: &Depot) -> bool {
+    let Some(token) = depot.jwt_auth_token() else {
+        return false;
+    };
+    req.headers().get(AUTHORIZATION).is_none()
+        && req.query::<String>("token").is_none()
+        && req.cookie(TOKEN_COOKIE).is_some_and(|cookie| cookie.value() == token)
+}
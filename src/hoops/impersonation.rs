@@ -0,0 +1,43 @@
+use salvo::prelude::*;
+
+use super::jwt;
+use crate::audit::{self, Event};
+use crate::error::ErrorCode;
+use crate::AppResult;
+
+/// Keeps impersonated sessions away from credentials and payments. Mount it
+/// after `auth_hoop` on every such route.
+#[handler]
+pub async fn forbid_impersonation_hoop(depot: &mut Depot) -> AppResult<()> {
+    if jwt::current_claims(depot)?.is_impersonated() {
+        return Err(ErrorCode::ImpersonationForbidden.into());
+    }
+    Ok(())
+}
+
+/// Audits every request made with an impersonation token, whatever its outcome.
+#[handler]
+pub async fn impersonation_audit_hoop(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    ctrl.call_next(req, depot, res).await;
+
+    let Ok(claims) = jwt::current_claims(depot) else {
+        return;
+    };
+    if !claims.is_impersonated() {
+        return;
+    }
+    let route = format!("{} /{}", req.method(), req.matched_path());
+    let status = res.status_code.unwrap_or(StatusCode::OK).as_u16();
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_IMPERSONATION_REQUEST,
+            target_type: Some("route"),
+            target_id: Some(&route),
+            diff: Some(serde_json::json!({ "status": status })),
+            ..Default::default()
+        },
+    )
+    .await;
+}
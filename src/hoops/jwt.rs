@@ -1,58 +1,327 @@
 use anyhow::Result;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, EncodingKey, Validation};
-use salvo::jwt_auth::{ConstDecoder, CookieFinder, HeaderFinder, QueryFinder};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Header, TokenData};
+use salvo::async_trait;
+use salvo::http::header::AUTHORIZATION;
+use salvo::jwt_auth::{CookieFinder, HeaderFinder, JwtAuthDecoder, JwtTokenFinder, QueryFinder};
 use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use time::{Duration, OffsetDateTime};
 
-use crate::config::{self, JwtConfig};
+use crate::utils::{api_key, session};
+use crate::{config, jwt_keys, scopes, AppResult};
+
+/// Carries the session token for pages and same-site scripts.
+pub const TOKEN_COOKIE: &str = "jwt_token";
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JwtClaims {
     pub uid: String,
     exp: i64,
+    /// Missing from tokens issued before scopes existed; those get [`scopes::USER_DEFAULT`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    /// Set when the request was authenticated with an API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<String>,
+    /// Login session the token belongs to; revoking it invalidates the token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// The admin acting as `uid` through `POST /api/admin/users/{id}/impersonate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<String>,
 }
 
 impl JwtClaims {
-    #[allow(dead_code)]
     pub fn user_id(&self) -> &str {
         &self.uid
     }
+
+    pub fn is_api_key(&self) -> bool {
+        self.api_key_id.is_some()
+    }
+
+    pub fn is_impersonated(&self) -> bool {
+        self.impersonator_id.is_some()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|granted| granted == scope),
+            None => scopes::USER_DEFAULT.contains(&scope),
+        }
+    }
+}
+
+/// Issued after the password check when the account has two-factor enabled.
+///
+/// It has no `uid`, so `auth_hoop` cannot decode it as [`JwtClaims`] and it is
+/// only good for `POST /api/login/mfa`. The `jti` is also stored as a one-time
+/// token so it can complete a single login.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MfaPendingClaims {
+    pub mfa_uid: String,
+    pub jti: String,
+    exp: i64,
+}
+
+/// Carried by emailed login links. The `jti` is also stored as a one-time token
+/// so each link can only be used once.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MagicLinkClaims {
+    pub link_uid: String,
+    pub jti: String,
+    pub redirect: String,
+    exp: i64,
+}
+
+/// Kept in the `oidc_state` cookie between the redirect to a social login
+/// provider and its callback.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OidcStateClaims {
+    pub oidc_provider: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    exp: i64,
+}
+
+/// Carried by the download link of a finished personal data export.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DataExportClaims {
+    pub export_id: String,
+    pub export_uid: String,
+    exp: i64,
+}
+
+/// Claims of the request authenticated by `auth_hoop`.
+pub fn current_claims(depot: &Depot) -> AppResult<&JwtClaims> {
+    match depot.jwt_auth_state() {
+        JwtAuthState::Authorized => depot
+            .jwt_auth_data::<JwtClaims>()
+            .map(|data| &data.claims)
+            .ok_or_else(|| StatusError::unauthorized().into()),
+        _ => Err(StatusError::unauthorized().into()),
+    }
+}
+
+/// Finds `Authorization: ApiKey <key>`.
+pub struct ApiKeyFinder;
+
+#[async_trait]
+impl JwtTokenFinder for ApiKeyFinder {
+    async fn find_token(&self, req: &mut Request) -> Option<String> {
+        req.headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("ApiKey "))
+            .map(|key| key.trim().to_owned())
+    }
 }
 
-pub fn auth_hoop(config: &JwtConfig) -> JwtAuth<JwtClaims, ConstDecoder> {
-    JwtAuth::new(ConstDecoder::from_secret(
-        config.secret.to_owned().as_bytes(),
-    ))
-    .finders(vec![
-        Box::new(HeaderFinder::new()),
-        Box::new(QueryFinder::new("token")),
-        Box::new(CookieFinder::new("jwt_token")),
-    ])
-    .force_passed(false)
+/// Verifies session tokens against every configured key, picked by `kid`, and
+/// turns API keys into the same claims a session token would carry.
+pub struct KeySetDecoder;
+
+impl JwtAuthDecoder for KeySetDecoder {
+    type Error = jsonwebtoken::errors::Error;
+
+    async fn decode<C>(&self, token: &str, _depot: &mut Depot) -> Result<TokenData<C>, Self::Error>
+    where
+        C: for<'de> Deserialize<'de> + Clone,
+    {
+        if token.starts_with(api_key::KEY_PREFIX) {
+            return decode_api_key(token).await;
+        }
+        let data = jwt_keys::get().decode::<serde_json::Value>(token)?;
+        if let Some(sid) = data.claims.get("sid").and_then(|sid| sid.as_str()) {
+            let active = session::check_and_touch(sid).await.map_err(|e| {
+                tracing::error!(error = ?e, "session lookup failed");
+                ErrorKind::InvalidToken
+            })?;
+            if !active {
+                return Err(ErrorKind::InvalidToken.into());
+            }
+        }
+        Ok(TokenData {
+            header: data.header,
+            claims: serde_json::from_value(data.claims)?,
+        })
+    }
+}
+
+async fn decode_api_key<C>(key: &str) -> Result<TokenData<C>, jsonwebtoken::errors::Error>
+where
+    C: for<'de> Deserialize<'de> + Clone,
+{
+    let row = api_key::authenticate(key)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, "api key lookup failed");
+            ErrorKind::InvalidToken
+        })?
+        .ok_or(ErrorKind::InvalidToken)?;
+    let exp = row
+        .expires_at
+        .map(|at| at.assume_utc().unix_timestamp())
+        .unwrap_or(i64::MAX);
+    let claims = serde_json::from_value(json!({
+        "uid": row.user_id,
+        "exp": exp,
+        "scopes": row.scopes.split_whitespace().collect::<Vec<_>>(),
+        "api_key_id": row.id,
+    }))?;
+    Ok(TokenData {
+        header: Header::default(),
+        claims,
+    })
 }
 
-pub fn get_token(uid: impl Into<String>) -> Result<(String, i64)> {
+pub fn auth_hoop() -> JwtAuth<JwtClaims, KeySetDecoder> {
+    JwtAuth::new(KeySetDecoder)
+        .finders(vec![
+            Box::new(ApiKeyFinder),
+            Box::new(HeaderFinder::new()),
+            Box::new(QueryFinder::new("token")),
+            Box::new(CookieFinder::new(TOKEN_COOKIE)),
+        ])
+        .force_passed(false)
+}
+
+/// Like [`auth_hoop`], for server-rendered pages: only the `jwt_token` cookie is
+/// looked at and requests without a valid one still reach the handler, which
+/// decides where to send them.
+pub fn page_auth_hoop() -> JwtAuth<JwtClaims, KeySetDecoder> {
+    JwtAuth::new(KeySetDecoder)
+        .finders(vec![Box::new(CookieFinder::new(TOKEN_COOKIE))])
+        .force_passed(true)
+}
+
+pub fn get_token(
+    uid: impl Into<String>,
+    sid: impl Into<String>,
+    scopes: Vec<String>,
+) -> Result<(String, i64)> {
     let exp = OffsetDateTime::now_utc() + Duration::seconds(config::get().jwt.expiry);
     let claim = JwtClaims {
         uid: uid.into(),
         exp: exp.unix_timestamp(),
+        scopes: Some(scopes),
+        api_key_id: None,
+        sid: Some(sid.into()),
+        impersonator_id: None,
+    };
+    let token = jwt_keys::get().encode(&claim)?;
+    Ok((token, exp.unix_timestamp()))
+}
+
+/// A short-lived session token for `uid` that records `impersonator_id` as the real actor.
+pub fn get_impersonation_token(
+    uid: impl Into<String>,
+    impersonator_id: impl Into<String>,
+    scopes: Vec<String>,
+) -> Result<(String, i64)> {
+    let exp = OffsetDateTime::now_utc() + Duration::seconds(config::get().jwt.impersonation_expiry);
+    let claim = JwtClaims {
+        uid: uid.into(),
+        exp: exp.unix_timestamp(),
+        scopes: Some(scopes),
+        api_key_id: None,
+        sid: None,
+        impersonator_id: Some(impersonator_id.into()),
     };
-    let token: String = jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &claim,
-        &EncodingKey::from_secret(config::get().jwt.secret.as_bytes()),
-    )?;
+    let token = jwt_keys::get().encode(&claim)?;
     Ok((token, exp.unix_timestamp()))
 }
 
-#[allow(dead_code)]
+pub fn get_mfa_pending_token(uid: impl Into<String>, jti: impl Into<String>) -> Result<(String, i64)> {
+    let exp = OffsetDateTime::now_utc() + Duration::seconds(config::get().mfa.pending_expiry);
+    let claim = MfaPendingClaims {
+        mfa_uid: uid.into(),
+        jti: jti.into(),
+        exp: exp.unix_timestamp(),
+    };
+    let token = jwt_keys::get().encode(&claim)?;
+    Ok((token, exp.unix_timestamp()))
+}
+
+/// Returns the claims of a valid, unexpired mfa pending token. Whether its
+/// `jti` was already used is up to the caller.
+pub fn decode_mfa_pending_token(token: &str) -> Option<MfaPendingClaims> {
+    jwt_keys::get()
+        .decode::<MfaPendingClaims>(token)
+        .ok()
+        .map(|data| data.claims)
+}
+
+pub fn get_magic_link_token(
+    uid: impl Into<String>,
+    jti: impl Into<String>,
+    redirect: impl Into<String>,
+) -> Result<String> {
+    let exp = OffsetDateTime::now_utc() + Duration::seconds(config::get().magic_link.expiry);
+    let claim = MagicLinkClaims {
+        link_uid: uid.into(),
+        jti: jti.into(),
+        redirect: redirect.into(),
+        exp: exp.unix_timestamp(),
+    };
+    Ok(jwt_keys::get().encode(&claim)?)
+}
+
+pub fn decode_magic_link_token(token: &str) -> Option<MagicLinkClaims> {
+    jwt_keys::get()
+        .decode::<MagicLinkClaims>(token)
+        .ok()
+        .map(|data| data.claims)
+}
+
+/// A download link token valid until `exp`, the export's own expiry.
+pub fn get_data_export_token(
+    export_id: impl Into<String>,
+    uid: impl Into<String>,
+    exp: i64,
+) -> Result<String> {
+    let claim = DataExportClaims {
+        export_id: export_id.into(),
+        export_uid: uid.into(),
+        exp,
+    };
+    Ok(jwt_keys::get().encode(&claim)?)
+}
+
+pub fn decode_data_export_token(token: &str) -> Option<DataExportClaims> {
+    jwt_keys::get()
+        .decode::<DataExportClaims>(token)
+        .ok()
+        .map(|data| data.claims)
+}
+
+pub fn get_oidc_state_token(
+    provider: impl Into<String>,
+    state: impl Into<String>,
+    nonce: impl Into<String>,
+    code_verifier: impl Into<String>,
+) -> Result<String> {
+    let exp = OffsetDateTime::now_utc() + Duration::seconds(config::get().oidc.state_expiry);
+    let claim = OidcStateClaims {
+        oidc_provider: provider.into(),
+        state: state.into(),
+        nonce: nonce.into(),
+        code_verifier: code_verifier.into(),
+        exp: exp.unix_timestamp(),
+    };
+    Ok(jwt_keys::get().encode(&claim)?)
+}
+
+pub fn decode_oidc_state_token(token: &str) -> Option<OidcStateClaims> {
+    jwt_keys::get()
+        .decode::<OidcStateClaims>(token)
+        .ok()
+        .map(|data| data.claims)
+}
+
 pub fn decode_token(token: &str) -> bool {
-    let validation = Validation::new(Algorithm::HS256);
-    decode::<JwtClaims>(
-        token,
-        &DecodingKey::from_secret(config::get().jwt.secret.as_bytes()),
-        &validation,
-    )
-    .is_ok()
+    jwt_keys::get().decode::<JwtClaims>(token).is_ok()
 }
@@ -0,0 +1,29 @@
+use std::time::Instant;
+
+use salvo::http::ResBody;
+use salvo::prelude::*;
+
+use crate::metrics::{HTTP_REQUESTS_TOTAL, HTTP_REQUEST_DURATION};
+
+/// Records request count and latency labelled by the matched route template,
+/// so that `{user_id}` style parameters don't create one series per value.
+#[handler]
+pub async fn metrics_hoop(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let started = Instant::now();
+    ctrl.call_next(req, depot, res).await;
+
+    let status = res.status_code.unwrap_or(match &res.body {
+        ResBody::None => StatusCode::NOT_FOUND,
+        ResBody::Error(e) => e.code,
+        _ => StatusCode::OK,
+    });
+    let route = match req.matched_path() {
+        "" if status == StatusCode::NOT_FOUND => "unmatched".to_owned(),
+        path => format!("/{path}"),
+    };
+    let labels = [req.method().as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS_TOTAL.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+}
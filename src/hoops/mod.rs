@@ -2,27 +2,47 @@ use rinja::Template;
 use salvo::http::ResBody;
 use salvo::prelude::*;
 
+use crate::i18n::{Locale, Tr};
+
 pub mod custom_middleware_example;
 pub mod jwt;
 pub use jwt::auth_hoop;
 mod cors;
 pub use cors::cors_hoop;
+pub mod csrf;
+pub use csrf::csrf_hoop;
+mod metrics;
+pub use metrics::metrics_hoop;
+mod trace_context;
+pub use trace_context::trace_context_hoop;
+mod admin_page;
+pub use admin_page::admin_page_hoop;
+mod impersonation;
+pub use impersonation::{forbid_impersonation_hoop, impersonation_audit_hoop};
+pub mod request_id;
+pub use request_id::request_id_hoop;
+pub mod rate_limit;
+pub mod scope;
+pub use scope::scope_hoop;
 
 #[derive(Template)]
 #[template(path = "error_404.html")]
 struct Error404 {
     brief: String,
+    tr: Tr,
 }
 
 #[handler]
-pub async fn error_404(&self, res: &mut Response, ctrl: &mut FlowCtrl) {
+pub async fn error_404(&self, req: &mut Request, res: &mut Response, ctrl: &mut FlowCtrl) {
     if let Some(StatusCode::NOT_FOUND) = res.status_code {
+        let tr = Tr(Locale::of(req));
         let handle404 = Error404 {
             brief: if let ResBody::Error(e) = &res.body {
-                e.brief.clone()
+                tr.t(&e.brief).into_owned()
             } else {
-                "Page not found".to_owned()
+                tr.t("Page not found").into_owned()
             },
+            tr,
         };
         res.render(Text::Html(handle404.render().unwrap()));
         ctrl.skip_rest();
@@ -0,0 +1,56 @@
+use salvo::prelude::*;
+use salvo::rate_limiter::{
+    BasicQuota, MokaStore, RateIssuer, RateLimiter, RemoteIpIssuer, FixedGuard,
+};
+use serde::Deserialize;
+
+use crate::config::QuotaConfig;
+
+type Limiter<I> = RateLimiter<FixedGuard, MokaStore<String, FixedGuard>, I, BasicQuota>;
+
+impl QuotaConfig {
+    fn quota(&self) -> BasicQuota {
+        BasicQuota::set_seconds(self.limit, self.period_seconds)
+    }
+}
+
+/// Limits requests per client IP address.
+pub fn ip_limiter(quota: &QuotaConfig) -> Limiter<RemoteIpIssuer> {
+    RateLimiter::new(FixedGuard::new(), MokaStore::new(), RemoteIpIssuer, quota.quota())
+        .add_headers(true)
+}
+
+/// Limits requests per target account, keyed by the `email` field of the JSON
+/// body. Requests without one are limited per IP address instead.
+pub fn account_limiter(quota: &QuotaConfig) -> Limiter<EmailIssuer> {
+    RateLimiter::new(FixedGuard::new(), MokaStore::new(), EmailIssuer, quota.quota())
+        .add_headers(true)
+}
+
+pub struct EmailIssuer;
+
+impl RateIssuer for EmailIssuer {
+    type Key = String;
+
+    async fn issue(&self, req: &mut Request, depot: &Depot) -> Option<Self::Key> {
+        #[derive(Deserialize)]
+        struct EmailOnly {
+            #[serde(default)]
+            email: String,
+        }
+        // The payload is cached by salvo, so the handler can still extract the body.
+        let email = req
+            .parse_json::<EmailOnly>()
+            .await
+            .map(|body| body.email)
+            .unwrap_or_default();
+        let email = email.trim().to_lowercase();
+        // Otherwise every body without an email would share one bucket, and a
+        // single client could use it up for everybody.
+        if email.is_empty() {
+            let ip = RemoteIpIssuer.issue(req, depot).await?;
+            return Some(format!("ip:{ip}"));
+        }
+        Some(format!("email:{email}"))
+    }
+}
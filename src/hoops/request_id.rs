@@ -0,0 +1,42 @@
+use salvo::http::HeaderValue;
+use salvo::prelude::*;
+use tracing::Instrument;
+use ulid::Ulid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const REQUEST_ID_KEY: &str = "request_id";
+
+/// Accepts the caller's `X-Request-Id` or generates a ULID, then makes it
+/// available to logs, handlers (through the `Depot`) and the response headers.
+#[handler]
+pub async fn request_id_hoop(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| is_acceptable(v))
+        .map(str::to_owned)
+        .unwrap_or_else(|| Ulid::new().to_string());
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let span = tracing::info_span!("request", request_id = %request_id);
+    depot.insert(REQUEST_ID_KEY, request_id);
+
+    ctrl.call_next(req, depot, res).instrument(span).await;
+}
+
+/// The id assigned to the current request by [`request_id_hoop`].
+pub fn request_id(depot: &Depot) -> Option<&str> {
+    depot.get::<String>(REQUEST_ID_KEY).ok().map(String::as_str)
+}
+
+/// Client supplied ids end up in our logs, so only accept short, plain tokens.
+fn is_acceptable(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
@@ -0,0 +1,87 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::OnceLock;
+
+use salvo::oapi::OpenApi;
+use salvo::oapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use salvo::prelude::*;
+
+use super::jwt;
+use crate::error::ErrorCode;
+use crate::i18n::Message;
+use crate::{AppError, AppResult};
+
+pub const BEARER_SCHEME: &str = "bearer";
+pub const API_KEY_SCHEME: &str = "api_key";
+
+/// Scopes required per `(METHOD, /path/{template})`, read from the OpenAPI document.
+static REQUIRED_SCOPES: OnceLock<HashMap<(String, String), Vec<String>>> = OnceLock::new();
+
+/// Declares the security schemes endpoints refer to and records the scopes each
+/// endpoint asks for, so the document stays the single source of truth.
+pub fn register(doc: OpenApi) -> OpenApi {
+    let doc = doc
+        .add_security_scheme(
+            BEARER_SCHEME,
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer).bearer_format("JWT")),
+        )
+        .add_security_scheme(
+            API_KEY_SCHEME,
+            SecurityScheme::ApiKey(ApiKey::Header(
+                ApiKeyValue::with_description("Authorization", "`ApiKey <key>`"),
+            )),
+        );
+    let _ = REQUIRED_SCOPES.set(required_scopes(&doc));
+    doc
+}
+
+fn required_scopes(doc: &OpenApi) -> HashMap<(String, String), Vec<String>> {
+    let mut required = HashMap::new();
+    let Ok(serde_json::Value::Object(paths)) = serde_json::to_value(&doc.paths) else {
+        return required;
+    };
+    for (path, item) in paths {
+        let Some(operations) = item.as_object() else {
+            continue;
+        };
+        for (method, operation) in operations {
+            let Some(securities) = operation.get("security").and_then(|v| v.as_array()) else {
+                continue;
+            };
+            let scopes: BTreeSet<String> = securities
+                .iter()
+                .filter_map(|requirement| requirement.as_object())
+                .flat_map(|requirement| requirement.values())
+                .filter_map(|scopes| scopes.as_array())
+                .flatten()
+                .filter_map(|scope| scope.as_str().map(str::to_owned))
+                .collect();
+            if !scopes.is_empty() {
+                required.insert(
+                    (method.to_ascii_uppercase(), path.clone()),
+                    scopes.into_iter().collect(),
+                );
+            }
+        }
+    }
+    required
+}
+
+/// Rejects tokens lacking a scope the matched endpoint declares. Mount it after `auth_hoop`.
+#[handler]
+pub async fn scope_hoop(req: &mut Request, depot: &mut Depot) -> AppResult<()> {
+    let key = (
+        req.method().as_str().to_owned(),
+        format!("/{}", req.matched_path()),
+    );
+    let Some(required) = REQUIRED_SCOPES.get().and_then(|required| required.get(&key)) else {
+        return Ok(());
+    };
+    let claims = jwt::current_claims(depot)?;
+    if let Some(missing) = required.iter().find(|scope| !claims.has_scope(scope)) {
+        return Err(AppError::business(
+            ErrorCode::ScopeMissing,
+            Message::new("This token lacks the {scope} scope.").arg("scope", missing),
+        ));
+    }
+    Ok(())
+}
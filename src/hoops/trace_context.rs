@@ -0,0 +1,31 @@
+use salvo::prelude::*;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::telemetry;
+
+/// Continues the caller's W3C trace (if any) and returns our `traceparent`
+/// so clients can correlate their own spans with ours.
+#[handler]
+pub async fn trace_context_hoop(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let span = tracing::info_span!(
+        "http.request",
+        otel.kind = "server",
+        http.request.method = %req.method(),
+        http.route = tracing::field::Empty,
+        http.response.status_code = tracing::field::Empty,
+    );
+    let _ = span.set_parent(telemetry::extract_context(req.headers()));
+    telemetry::inject_context(&span, res.headers_mut());
+
+    async {
+        ctrl.call_next(req, depot, res).await;
+        let span = tracing::Span::current();
+        span.record("http.route", format!("/{}", req.matched_path()));
+        if let Some(status) = res.status_code {
+            span.record("http.response.status_code", status.as_u16());
+        }
+    }
+    .instrument(span)
+    .await
+}
@@ -0,0 +1,209 @@
+//! Translations of user-facing text.
+//!
+//! Messages are written in English in the code and double as catalog keys, so
+//! English needs no catalog and a missing translation falls back to English.
+//! Placeholders look like `{name}` and are filled from [`Message::arg`].
+//!
+//! The locale of a request comes from the `locale` cookie, which holds the
+//! user's saved preference, then `Accept-Language`, then `i18n.default_locale`.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::LazyLock;
+
+use cookie::Cookie;
+use salvo::http::header::ACCEPT_LANGUAGE;
+use salvo::prelude::*;
+
+use crate::config;
+
+pub const LOCALE_COOKIE: &str = "locale";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Locale {
+    #[default]
+    En,
+    ZhCn,
+}
+
+impl Locale {
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::ZhCn => "zh-CN",
+        }
+    }
+
+    /// Matches a BCP 47 tag by its primary language, so `en-GB` is English and
+    /// every Chinese variant gets the simplified catalog.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim().to_ascii_lowercase();
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        match language {
+            "en" => Some(Self::En),
+            "zh" => Some(Self::ZhCn),
+            _ => None,
+        }
+    }
+
+    /// The supported locale the client ranks highest in an `Accept-Language` header.
+    pub fn negotiate(accept_language: &str) -> Option<Self> {
+        let mut ranked: Vec<(f32, &str)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                Some((quality, tag))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranked.into_iter().find_map(|(_, tag)| Self::parse(tag))
+    }
+
+    /// The locale to answer `req` in.
+    pub fn of(req: &Request) -> Self {
+        req.cookie(LOCALE_COOKIE)
+            .and_then(|cookie| Self::parse(cookie.value()))
+            .or_else(|| {
+                req.headers()
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(Self::negotiate)
+            })
+            .unwrap_or_else(default_locale)
+    }
+
+    /// An account's saved locale, or the configured default. For mail, which
+    /// has no request to negotiate with.
+    pub fn saved_or_default(tag: Option<&str>) -> Self {
+        tag.and_then(Self::parse).unwrap_or_else(default_locale)
+    }
+}
+
+/// Remembers `locale` in the browser, so pages and errors use it before any
+/// `Accept-Language`.
+pub fn locale_cookie(locale: Locale) -> Cookie<'static> {
+    config::get()
+        .cookie
+        .build(LOCALE_COOKIE, locale.tag())
+        .max_age(cookie::time::Duration::days(365))
+        .build()
+}
+
+fn default_locale() -> Locale {
+    Locale::parse(&config::get().i18n.default_locale).unwrap_or_default()
+}
+
+static ZH_CN: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
+    serde_json::from_str(include_str!("../locales/zh-CN.json")).expect("zh-CN catalog should be valid JSON")
+});
+
+/// `text` in `locale`, or `text` itself when it has no translation.
+pub fn translate(locale: Locale, text: &str) -> Cow<'_, str> {
+    let catalog = match locale {
+        Locale::En => return Cow::Borrowed(text),
+        Locale::ZhCn => &*ZH_CN,
+    };
+    match catalog.get(text) {
+        Some(translated) => Cow::Owned(translated.clone()),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// User-facing text with named arguments, translated when it is rendered.
+#[derive(Clone, Debug)]
+pub struct Message {
+    text: Cow<'static, str>,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    pub fn new(text: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            text: text.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Fills `{name}` in the text.
+    pub fn arg(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    pub fn render(&self, locale: Locale) -> String {
+        let mut text = translate(locale, &self.text).into_owned();
+        for (name, value) in &self.args {
+            text = text.replace(&format!("{{{name}}}"), value);
+        }
+        text
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(Locale::En))
+    }
+}
+
+impl From<&'static str> for Message {
+    fn from(text: &'static str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+/// Translator handed to templates: `{{ tr.t("Login") }}`.
+#[derive(Clone, Copy, Debug)]
+pub struct Tr(pub Locale);
+
+impl Tr {
+    pub fn t<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        translate(self.0, text)
+    }
+
+    pub fn lang(&self) -> &'static str {
+        self.0.tag()
+    }
+
+    /// `text` translated with `{name}` filled: `{{ tr.fill("Hi {email},", "email", email) }}`.
+    pub fn fill(&self, text: &'static str, name: &'static str, value: impl fmt::Display) -> String {
+        Message::new(text).arg(name, value).render(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_by_quality() {
+        assert_eq!(Locale::negotiate("zh-CN,zh;q=0.9,en;q=0.8"), Some(Locale::ZhCn));
+        assert_eq!(Locale::negotiate("fr;q=1, en-US;q=0.7, zh;q=0.5"), Some(Locale::En));
+        assert_eq!(Locale::negotiate("zh-TW;q=0, de"), None);
+    }
+
+    #[test]
+    fn every_catalog_entry_keeps_its_placeholders() {
+        for (source, translated) in ZH_CN.iter() {
+            for placeholder in source.split('{').skip(1).filter_map(|rest| rest.split_once('}')) {
+                let placeholder = format!("{{{}}}", placeholder.0);
+                assert!(translated.contains(&placeholder), "{source} -> {translated}");
+            }
+        }
+        assert_eq!(
+            Message::new("Cannot sort by {name}.").arg("name", "x").render(Locale::ZhCn),
+            "不能按 x 排序。"
+        );
+    }
+}
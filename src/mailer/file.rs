@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use salvo::async_trait;
+use ulid::Ulid;
+
+use super::{Mail, Mailer};
+
+/// Writes messages to a directory, or to stdout, instead of delivering them.
+///
+/// Meant for development and tests, where the links inside the emails are
+/// all that matters.
+pub struct FileMailer {
+    directory: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn directory(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: Some(directory.into()),
+        }
+    }
+
+    pub fn stdout() -> Self {
+        Self { directory: None }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        let content = format!("To: {}\nSubject: {}\n\n{}\n", mail.to, mail.subject, mail.html);
+        match &self.directory {
+            Some(directory) => {
+                tokio::fs::create_dir_all(directory).await?;
+                let path = directory.join(format!("{}.eml", Ulid::new()));
+                tokio::fs::write(&path, content).await?;
+                tracing::info!(to = %mail.to, path = %path.display(), "mail written to file");
+            }
+            None => println!("{content}"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_mailer_writes_one_file_per_mail() {
+        let directory = std::env::temp_dir().join(format!("ttbox-mails-{}", Ulid::new()));
+        let mailer = FileMailer::directory(&directory);
+        mailer
+            .send(Mail {
+                to: "zhangsan@example.com".into(),
+                subject: "Verify your email address".into(),
+                html: "<a href=\"http://localhost/verify-email?token=abc\">verify</a>".into(),
+            })
+            .await
+            .unwrap();
+
+        let mut entries = std::fs::read_dir(&directory).unwrap();
+        let content = std::fs::read_to_string(entries.next().unwrap().unwrap().path()).unwrap();
+        assert!(content.starts_with("To: zhangsan@example.com\nSubject: Verify your email address"));
+        assert!(content.contains("verify-email?token=abc"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::OnceLock;
+
+use salvo::async_trait;
+
+use crate::config::{self, MailConfig};
+
+mod file;
+pub use file::FileMailer;
+mod smtp;
+pub use smtp::SmtpMailer;
+
+/// A rendered email ready to hand to a [`Mailer`].
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub html: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()>;
+}
+
+pub static MAILER: OnceLock<Box<dyn Mailer>> = OnceLock::new();
+
+pub fn init(config: &MailConfig) {
+    let mailer: Box<dyn Mailer> = match config.backend.as_str() {
+        config::MAIL_BACKEND_SMTP => match SmtpMailer::new(config) {
+            Ok(mailer) => Box::new(mailer),
+            Err(e) => {
+                eprintln!("It looks like your mail config is invalid: {e}");
+                std::process::exit(1);
+            }
+        },
+        config::MAIL_BACKEND_FILE => Box::new(FileMailer::directory(&config.directory)),
+        config::MAIL_BACKEND_STDOUT => Box::new(FileMailer::stdout()),
+        other => {
+            eprintln!("Unknown mail backend `{other}`, expected smtp, file or stdout");
+            std::process::exit(1);
+        }
+    };
+    if MAILER.set(mailer).is_err() {
+        panic!("mailer should be set once");
+    }
+}
+
+pub fn get() -> &'static dyn Mailer {
+    MAILER.get().expect("mailer should be set").as_ref()
+}
+
+/// Sends in a background task so request latency doesn't depend on the mail
+/// server, nor reveal whether a message was sent at all.
+pub fn send_later(mail: Mail) {
+    tokio::spawn(async move {
+        let to = mail.to.clone();
+        if let Err(e) = get().send(mail).await {
+            tracing::error!(error = ?e, to = %to, "failed to send mail");
+        }
+    });
+}
@@ -0,0 +1,48 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use salvo::async_trait;
+
+use super::{Mail, Mailer};
+use crate::config::MailConfig;
+
+pub struct SmtpMailer {
+    from: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> anyhow::Result<Self> {
+        let smtp = config
+            .smtp
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("[mail.smtp] is required for the smtp backend"))?;
+        let mut builder = if smtp.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?
+        }
+        .port(smtp.port);
+        if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(Self {
+            from: config.from.clone(),
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(self.from.parse()?)
+            .to(mail.to.parse()?)
+            .subject(mail.subject)
+            .header(ContentType::TEXT_HTML)
+            .body(mail.html)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
@@ -6,15 +6,24 @@ use serde::Serialize;
 use tokio::signal;
 use tracing::info;
 
+mod audit;
 mod config;
 mod db;
 mod hoops;
+mod i18n;
+mod jwt_keys;
+mod mailer;
+mod metrics;
 mod models;
+mod oidc;
+mod purge;
 mod entities;
 mod routers;
+mod scopes;
+mod telemetry;
 mod utils;
 
-mod error;
+pub mod error;
 pub use error::AppError;
 
 #[derive(Serialize, ToSchema, Clone, Copy, Debug)]
@@ -27,6 +36,19 @@ pub struct ApiResponse<T> {
     pub data: T,
 }
 
+/// `data` of every paginated list.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Paginated<T> {
+    pub data: Vec<T>,
+    pub total: u64,
+    pub current_page: u64,
+    pub page_size: u64,
+    /// Pass as `cursor` to get the following page, on lists that take one;
+    /// absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
 pub type AppResult<T> = Result<T, AppError>;
 pub type JsonResult<T> = Result<Json<ApiResponse<T>>, AppError>;
 pub type EmptyResult = Result<Json<ApiResponse<Empty>>, AppError>;
@@ -51,14 +73,30 @@ pub fn empty_ok() -> EmptyResult {
 async fn main() {
     crate::config::init();
     let config = crate::config::get();
+    crate::jwt_keys::init(&config.jwt);
+    crate::utils::init_dummy_password();
     crate::db::init(&config.db).await;
+    crate::purge::spawn(&config.soft_delete, &config.privacy);
 
-    let _guard = config.log.guard();
+    let tracer_provider = crate::telemetry::init(&config.telemetry);
+    let _guard = config
+        .log
+        .guard(tracer_provider.as_ref().map(crate::telemetry::tracer));
     tracing::info!("log level: {}", &config.log.filter_level);
 
+    crate::metrics::init();
+    crate::mailer::init(&config.mail);
+    if let (true, Some(metrics_addr)) = (config.metrics.enabled, &config.metrics.listen_addr) {
+        println!("📈 Metrics on http://{}/metrics", metrics_addr);
+        let acceptor = TcpListener::new(metrics_addr).bind().await;
+        let server = Server::new(acceptor);
+        tokio::spawn(shutdown_signal(server.handle()));
+        tokio::spawn(server.serve(Service::new(routers::metrics_router())));
+    }
+
     let service = Service::new(routers::root())
         .catcher(Catcher::default().hoop(hoops::error_404))
-        .hoop(hoops::cors_hoop());
+        .hoop(hoops::cors_hoop(&config.cors));
     println!("🔄 listen on {}", &config.listen_addr);
     //Acme support, automatically get TLS certificate from Let's Encrypt. For example, see https://github.com/salvo-rs/salvo/blob/main/examples/acme-http01-quinn/src/main.rs
     if let Some(tls) = &config.tls {
@@ -90,6 +128,12 @@ async fn main() {
         tokio::spawn(shutdown_signal(server.handle()));
         server.serve(service).await;
     }
+
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        eprintln!("failed to flush traces: {e}");
+    }
 }
 
 async fn shutdown_signal(handle: ServerHandle) {
@@ -119,26 +163,233 @@ async fn shutdown_signal(handle: ServerHandle) {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Once;
+
+    use salvo::catcher::Catcher;
     use salvo::prelude::*;
     use salvo::test::{ResponseExt, TestClient};
 
     use crate::config;
 
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(config::init);
+    }
+
+    fn base_url() -> String {
+        format!(
+            "http://{}",
+            config::get().listen_addr.replace("0.0.0.0", "127.0.0.1")
+        )
+    }
+
     #[tokio::test]
     async fn test_hello_world() {
-        config::init();
+        init();
 
         let service = Service::new(crate::routers::root());
 
-        let content = TestClient::get(format!(
-            "http://{}",
-            config::get().listen_addr.replace("0.0.0.0", "127.0.0.1")
-        ))
-        .send(&service)
-        .await
-        .take_string()
-        .await
-        .unwrap();
+        let content = TestClient::get(base_url())
+            .send(&service)
+            .await
+            .take_string()
+            .await
+            .unwrap();
         assert_eq!(content, "Hello World from salvo");
     }
+
+    #[tokio::test]
+    async fn test_metrics_use_route_template() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        TestClient::get(base_url()).send(&service).await;
+        let content = TestClient::get(format!("{}/metrics", base_url()))
+            .send(&service)
+            .await
+            .take_string()
+            .await
+            .unwrap();
+        assert!(content.contains(r#"http_requests_total{method="GET",route="/",status="200"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_admin_pages_redirect_to_login() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        let res = TestClient::get(format!("{}/users", base_url()))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::SEE_OTHER));
+        assert_eq!(res.headers().get("location").unwrap(), "/login");
+    }
+
+    #[tokio::test]
+    async fn test_not_found_page_follows_accept_language() {
+        init();
+
+        let service = Service::new(crate::routers::root())
+            .catcher(Catcher::default().hoop(crate::hoops::error_404));
+
+        let content = TestClient::get(format!("{}/no-such-page", base_url()))
+            .add_header("accept-language", "zh-CN,zh;q=0.9,en;q=0.8", true)
+            .send(&service)
+            .await
+            .take_string()
+            .await
+            .unwrap();
+        assert!(content.contains(r#"<html lang="zh-CN">"#));
+        assert!(content.contains("返回首页"));
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_echoed() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        let res = TestClient::get(base_url())
+            .add_header("x-request-id", "support-1234", true)
+            .send(&service)
+            .await;
+        assert_eq!(res.headers().get("x-request-id").unwrap(), "support-1234");
+
+        let res = TestClient::get(base_url()).send(&service).await;
+        assert_eq!(res.headers().get("x-request-id").unwrap().len(), 26);
+    }
+
+    #[tokio::test]
+    async fn test_magic_link_rejects_unlisted_redirect() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        let res = TestClient::post(format!("{}/api/login/magic-link", base_url()))
+            .json(&serde_json::json!({
+                "email": "someone@example.com",
+                "redirect": "https://evil.example.com/",
+            }))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_jwks_is_published() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        let content = TestClient::get(format!("{}/.well-known/jwks.json", base_url()))
+            .send(&service)
+            .await
+            .take_string()
+            .await
+            .unwrap();
+        assert_eq!(content, r#"{"keys":[]}"#);
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_scopes_are_enforced() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        // Without a session id, so the token is checked without a database.
+        let claims = serde_json::json!({
+            "uid": "someone",
+            "exp": time::OffsetDateTime::now_utc().unix_timestamp() + 60,
+            "scopes": [],
+        });
+        let token = crate::jwt_keys::get().encode(&claims).unwrap();
+        let res = TestClient::get(format!("{}/api/users", base_url()))
+            .bearer_auth(token)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::FORBIDDEN));
+
+        let doc = TestClient::get(format!("{}/api-doc/openapi.json", base_url()))
+            .send(&service)
+            .await
+            .take_json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(
+            doc["paths"]["/api/users"]["get"]["security"][0]["bearer"][0],
+            "users:read"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie_writes_require_csrf_token() {
+        init();
+
+        let service = Service::new(crate::routers::root());
+
+        // No scopes, so a request that gets past the CSRF check fails on scope instead.
+        let claims = serde_json::json!({
+            "uid": "someone",
+            "exp": time::OffsetDateTime::now_utc().unix_timestamp() + 60,
+            "scopes": [],
+        });
+        let token = crate::jwt_keys::get().encode(&claims).unwrap();
+        let url = format!("{}/api/users/someone", base_url());
+        let code = |mut res: salvo::Response| async move {
+            res.take_json::<serde_json::Value>().await.unwrap()["code"].clone()
+        };
+
+        let res = TestClient::delete(&url)
+            .add_header("cookie", format!("jwt_token={token}; csrf_token=abc"), true)
+            .send(&service)
+            .await;
+        assert_eq!(code(res).await, 30003);
+
+        let res = TestClient::delete(&url)
+            .add_header("cookie", format!("jwt_token={token}; csrf_token=abc"), true)
+            .add_header("x-csrf-token", "abc", true)
+            .send(&service)
+            .await;
+        assert_eq!(code(res).await, 30001);
+
+        let res = TestClient::delete(&url).bearer_auth(&token).send(&service).await;
+        assert_eq!(code(res).await, 30001);
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_allows_csrf_header() {
+        init();
+
+        let mut cors = config::CorsConfig::default();
+        cors.api.allowed_origins = vec!["https://*.example.com".into()];
+        cors.api.allow_credentials = true;
+        let service = Service::new(crate::routers::root()).hoop(crate::hoops::cors_hoop(&cors));
+
+        let res = TestClient::options(format!("{}/api/users/someone", base_url()))
+            .add_header("origin", "https://app.example.com", true)
+            .add_header("access-control-request-method", "DELETE", true)
+            .add_header("access-control-request-headers", "x-csrf-token", true)
+            .send(&service)
+            .await;
+        let headers = res.headers();
+        assert_eq!(headers.get("access-control-allow-origin").unwrap(), "https://app.example.com");
+        assert_eq!(headers.get("access-control-allow-credentials").unwrap(), "true");
+        assert!(
+            headers
+                .get("access-control-allow-headers")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .contains("x-csrf-token")
+        );
+
+        let res = TestClient::options(format!("{}/api/users/someone", base_url()))
+            .add_header("origin", "https://evil.test", true)
+            .add_header("access-control-request-method", "DELETE", true)
+            .send(&service)
+            .await;
+        assert!(res.headers().get("access-control-allow-origin").is_none());
+    }
 }
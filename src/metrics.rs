@@ -0,0 +1,93 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::db;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static HTTP_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("http_requests_total", "Total number of HTTP requests."),
+        &["method", "route", "status"],
+    ))
+});
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "HTTP request latency in seconds.",
+        ),
+        &["method", "route", "status"],
+    ))
+});
+
+pub static DB_POOL_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "db_pool_connections",
+        "Number of open connections in the database pool.",
+    ))
+});
+
+pub static DB_POOL_IDLE_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "db_pool_idle_connections",
+        "Number of idle connections in the database pool.",
+    ))
+});
+
+pub static LOGINS_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("logins_total", "Number of successful logins."))
+});
+
+pub static LOGIN_FAILURES_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "login_failures_total",
+        "Number of rejected login attempts.",
+    ))
+});
+
+pub static VIP_GRANTS_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "vip_grants_total",
+        "Number of times VIP status was granted to a user.",
+    ))
+});
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("metric options should be valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric should be registered once");
+    metric
+}
+
+/// Register every metric up front so they are exported before their first update.
+pub fn init() {
+    LazyLock::force(&HTTP_REQUESTS_TOTAL);
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&DB_POOL_CONNECTIONS);
+    LazyLock::force(&DB_POOL_IDLE_CONNECTIONS);
+    LazyLock::force(&LOGINS_TOTAL);
+    LazyLock::force(&LOGIN_FAILURES_TOTAL);
+    LazyLock::force(&VIP_GRANTS_TOTAL);
+}
+
+/// Refresh the pool gauges and encode every metric in the Prometheus text format.
+pub fn render() -> anyhow::Result<String> {
+    if let Some(conn) = db::SEAORM_POOL.get() {
+        let pool = conn.get_mysql_connection_pool();
+        DB_POOL_CONNECTIONS.set(pool.size() as i64);
+        DB_POOL_IDLE_CONNECTIONS.set(pool.num_idle() as i64);
+    }
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
@@ -82,6 +82,21 @@ pub struct SafeUser {
     pub updated_at: time::PrimitiveDateTime,
 }
 
+impl From<crate::entities::users::Model> for SafeUser {
+    fn from(user: crate::entities::users::Model) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            is_vip: user.is_vip,
+            vip_start_time: user.vip_start_time,
+            vip_end_time: user.vip_end_time,
+            vip_level: user.vip_level,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
 #[derive(Deserialize, ToSchema, Debug)]
 #[allow(dead_code)]
 pub struct RegisterUser {
@@ -0,0 +1,241 @@
+//! Background retention jobs: anonymises accounts whose self-deletion is due,
+//! removes expired data exports and hard-deletes users once their soft-delete
+//! retention period has passed.
+
+use std::time::Duration;
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect, Set,
+    TransactionTrait, Value,
+};
+
+use crate::audit::{self, Event};
+use crate::config::{PrivacyConfig, SoftDeleteConfig};
+use crate::entities::{prelude::*, *};
+use crate::routers::privacy::{EXPORT_FAILED, EXPORT_PENDING};
+use crate::{db, utils, AppResult};
+
+/// Users handled per transaction, so one run doesn't hold locks for long.
+const BATCH_SIZE: u64 = 100;
+
+/// Runs the jobs every `purge_interval` seconds in the background.
+pub fn spawn(config: &'static SoftDeleteConfig, privacy: &'static PrivacyConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.purge_interval.max(1)));
+        loop {
+            interval.tick().await;
+            match anonymize_due_accounts(privacy).await {
+                Ok(0) => {}
+                Ok(anonymized) => tracing::info!(anonymized, "anonymized deleted accounts"),
+                Err(e) => tracing::error!(error = ?e, "failed to anonymize deleted accounts"),
+            }
+            if let Err(e) = remove_expired_exports(privacy).await {
+                tracing::error!(error = ?e, "failed to remove expired data exports");
+            }
+            match purge_deleted_users(privacy, config.retention_days).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!(purged, "purged deleted users"),
+                Err(e) => tracing::error!(error = ?e, "failed to purge deleted users"),
+            }
+        }
+    });
+}
+
+/// Deletes every row that belongs to the given users, except the users
+/// themselves and their audit events. Returns the ids of the deleted exports,
+/// whose files the caller removes once the transaction has committed.
+async fn delete_owned_rows(conn: &impl ConnectionTrait, ids: &[String]) -> AppResult<Vec<String>> {
+    let exports: Vec<String> = DataExports::find()
+        .select_only()
+        .column(data_exports::Column::Id)
+        .filter(data_exports::Column::UserId.is_in(ids.to_vec()))
+        .into_tuple()
+        .all(conn)
+        .await?;
+    Sessions::delete_many()
+        .filter(sessions::Column::UserId.is_in(ids.to_vec()))
+        .exec(conn)
+        .await?;
+    ApiKeys::delete_many()
+        .filter(api_keys::Column::UserId.is_in(ids.to_vec()))
+        .exec(conn)
+        .await?;
+    UserIdentities::delete_many()
+        .filter(user_identities::Column::UserId.is_in(ids.to_vec()))
+        .exec(conn)
+        .await?;
+    UserTokens::delete_many()
+        .filter(user_tokens::Column::UserId.is_in(ids.to_vec()))
+        .exec(conn)
+        .await?;
+    UserRecoveryCodes::delete_many()
+        .filter(user_recovery_codes::Column::UserId.is_in(ids.to_vec()))
+        .exec(conn)
+        .await?;
+    DataExports::delete_many()
+        .filter(data_exports::Column::UserId.is_in(ids.to_vec()))
+        .exec(conn)
+        .await?;
+    Ok(exports)
+}
+
+/// Drops the diffs of audit events about the given users, which hold their
+/// email and other profile fields. The events themselves stay.
+async fn scrub_audit_diffs(conn: &impl ConnectionTrait, ids: &[String]) -> AppResult<()> {
+    AuditEvents::update_many()
+        .col_expr(audit_events::Column::Diff, Expr::value(Value::Json(None)))
+        .filter(audit_events::Column::TargetType.eq("user"))
+        .filter(audit_events::Column::TargetId.is_in(ids.to_vec()))
+        .filter(audit_events::Column::Diff.is_not_null())
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// Replaces the personal data of accounts whose self-deletion cooling-off has
+/// ended. The row stays, soft-deleted, so orders and audit history still
+/// point somewhere. Returns how many accounts were anonymised; one that fails
+/// is logged and left for the next run.
+pub async fn anonymize_due_accounts(privacy: &PrivacyConfig) -> AppResult<u64> {
+    let now = utils::now_primitive();
+    let due = Users::find()
+        .filter(users::Column::DeletionScheduledAt.lte(now))
+        .filter(users::Column::AnonymizedAt.is_null())
+        .limit(BATCH_SIZE)
+        .all(db::pool())
+        .await?;
+    let mut anonymized = 0;
+    for user in due {
+        let user_id = user.id.clone();
+        match anonymize_account(privacy, user, now).await {
+            Ok(()) => anonymized += 1,
+            Err(e) => tracing::error!(user_id = %user_id, error = ?e, "failed to anonymize deleted account"),
+        }
+    }
+    Ok(anonymized)
+}
+
+async fn anonymize_account(
+    privacy: &PrivacyConfig,
+    user: users::Model,
+    now: time::PrimitiveDateTime,
+) -> AppResult<()> {
+    let user_id = user.id.clone();
+    let txn = db::pool().begin().await?;
+    let exports = delete_owned_rows(&txn, std::slice::from_ref(&user_id)).await?;
+    scrub_audit_diffs(&txn, std::slice::from_ref(&user_id)).await?;
+    let mut user: users::ActiveModel = user.into();
+    user.email = Set(format!("deleted-{user_id}@anonymized.invalid"));
+    user.password = Set(utils::hash_password(&utils::random_string(32))?);
+    user.password_set = Set(false);
+    user.totp_secret = Set(None);
+    user.totp_enabled = Set(false);
+    user.totp_last_step = Set(None);
+    user.email_verified_at = Set(None);
+    user.failed_login_count = Set(0);
+    user.locked_until = Set(None);
+    user.locale = Set(None);
+    user.deleted_at = Set(Some(now));
+    user.deletion_scheduled_at = Set(None);
+    user.anonymized_at = Set(Some(now));
+    user.updated_at = Set(now);
+    user.update(&txn).await?;
+    txn.commit().await?;
+    for export_id in exports {
+        remove_export_file(privacy, &export_id).await;
+    }
+    audit::record_system(Event {
+        action: audit::ACTION_USER_ANONYMIZE,
+        target_type: Some("user"),
+        target_id: Some(&user_id),
+        ..Default::default()
+    })
+    .await;
+    Ok(())
+}
+
+/// Deletes exports, file and row, whose download window has passed, and marks
+/// exports pending for longer than `export_timeout` as failed.
+pub async fn remove_expired_exports(privacy: &PrivacyConfig) -> AppResult<()> {
+    let now = utils::now_primitive();
+    DataExports::update_many()
+        .col_expr(data_exports::Column::Status, Expr::value(EXPORT_FAILED))
+        .filter(data_exports::Column::Status.eq(EXPORT_PENDING))
+        .filter(data_exports::Column::CreatedAt.lt(now - time::Duration::seconds(privacy.export_timeout)))
+        .exec(db::pool())
+        .await?;
+    let expired = DataExports::find()
+        .filter(data_exports::Column::ExpiresAt.lt(now))
+        .all(db::pool())
+        .await?;
+    for export in expired {
+        remove_export_file(privacy, &export.id).await;
+        DataExports::delete_by_id(export.id).exec(db::pool()).await?;
+    }
+    Ok(())
+}
+
+async fn remove_export_file(privacy: &PrivacyConfig, export_id: &str) {
+    let path = privacy.export_path(export_id);
+    if let Err(e) = tokio::fs::remove_file(&path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(error = %e, path = %path.display(), "failed to remove data export");
+    }
+}
+
+/// Removes users deleted more than `retention_days` ago along with the rows
+/// that belong to them. Anonymised accounts and audit events, without their
+/// diffs, are kept. Returns how many users were purged; one that fails is
+/// logged and left for the next run.
+pub async fn purge_deleted_users(privacy: &PrivacyConfig, retention_days: i64) -> AppResult<u64> {
+    let cutoff = utils::now_primitive() - time::Duration::days(retention_days);
+    let mut purged = 0;
+    let mut failed: Vec<String> = Vec::new();
+    loop {
+        let ids: Vec<String> = Users::find()
+            .select_only()
+            .column(users::Column::Id)
+            .filter(users::Column::DeletedAt.lt(cutoff))
+            .filter(users::Column::AnonymizedAt.is_null())
+            .filter(users::Column::Id.is_not_in(failed.clone()))
+            .limit(BATCH_SIZE)
+            .into_tuple()
+            .all(db::pool())
+            .await?;
+        if ids.is_empty() {
+            return Ok(purged);
+        }
+        if let Ok(count) = purge_users(privacy, &ids).await {
+            purged += count;
+            continue;
+        }
+        // Retry one by one, so a single broken account doesn't hold up the batch.
+        for id in ids {
+            match purge_users(privacy, std::slice::from_ref(&id)).await {
+                Ok(count) => purged += count,
+                Err(e) => {
+                    tracing::error!(user_id = %id, error = ?e, "failed to purge deleted user");
+                    failed.push(id);
+                }
+            }
+        }
+    }
+}
+
+async fn purge_users(privacy: &PrivacyConfig, ids: &[String]) -> AppResult<u64> {
+    let txn = db::pool().begin().await?;
+    let exports = delete_owned_rows(&txn, ids).await?;
+    scrub_audit_diffs(&txn, ids).await?;
+    let purged = Users::delete_many()
+        .filter(users::Column::Id.is_in(ids.to_vec()))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    txn.commit().await?;
+    for export_id in exports {
+        remove_export_file(privacy, &export_id).await;
+    }
+    Ok(purged)
+}
@@ -0,0 +1,246 @@
+use rinja::Template;
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::{ActiveModelTrait, ColumnTrait, QueryFilter, Set};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::entities::{prelude::Users, users};
+use crate::hoops::jwt;
+use crate::mailer::{self, Mail};
+use crate::utils::{one_time_token, session};
+use crate::error::ErrorCode;
+use crate::i18n::{self, Locale, Message, Tr};
+use crate::{config, db, empty_ok, utils, AppError, AppResult, EmptyResult};
+
+#[derive(Template)]
+#[template(path = "emails/verify_email.html")]
+struct VerifyEmailMail<'a> {
+    email: &'a str,
+    link: &'a str,
+    expires_in_hours: i64,
+    tr: Tr,
+}
+
+#[derive(Template)]
+#[template(path = "emails/reset_password.html")]
+struct ResetPasswordMail<'a> {
+    email: &'a str,
+    link: &'a str,
+    expires_in_minutes: i64,
+    tr: Tr,
+}
+
+/// Issues a fresh verification token and mails the link to the user.
+pub async fn send_verification_email(user: &users::Model) -> AppResult<()> {
+    let mail_config = &config::get().mail;
+    let token = one_time_token::issue(
+        &user.id,
+        one_time_token::PURPOSE_VERIFY_EMAIL,
+        mail_config.verify_email_expiry,
+    )
+    .await?;
+    let link = format!("{}/verify-email?token={}", mail_config.link_base_url, token);
+    let tr = Tr(Locale::saved_or_default(user.locale.as_deref()));
+    let html = VerifyEmailMail {
+        email: &user.email,
+        link: &link,
+        expires_in_hours: mail_config.verify_email_expiry / 3600,
+        tr,
+    }
+    .render()
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    mailer::send_later(Mail {
+        to: user.email.clone(),
+        subject: tr.t("Verify your email address").into_owned(),
+        html,
+    });
+    Ok(())
+}
+
+/// Marks the email of the token's owner as verified. Returns whether the token was valid.
+async fn verify_email_token(token: &str) -> AppResult<bool> {
+    let Some(user_id) = one_time_token::consume(token, one_time_token::PURPOSE_VERIFY_EMAIL).await?
+    else {
+        return Ok(false);
+    };
+    let conn = db::pool();
+    let Some(user) = Users::find_active_by_id(user_id).one(conn).await? else {
+        return Ok(false);
+    };
+    if user.email_verified_at.is_none() {
+        let mut user: users::ActiveModel = user.into();
+        user.email_verified_at = Set(Some(utils::now_primitive()));
+        user.update(conn).await?;
+    }
+    Ok(true)
+}
+
+#[endpoint(tags("account"), security(("bearer" = []), ("api_key" = [])))]
+pub async fn resend_verification_email(depot: &mut Depot) -> EmptyResult {
+    let user_id = jwt::current_claims(depot)?.user_id();
+    let Some(user) = Users::find_active_by_id(user_id).one(db::pool()).await? else {
+        return Err(StatusError::unauthorized().into());
+    };
+    if user.email_verified_at.is_some() {
+        return Err(AppError::public("Email address is already verified."));
+    }
+    send_verification_email(&user).await?;
+    empty_ok()
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct TokenInData {
+    pub token: String,
+}
+
+#[endpoint(tags("account"))]
+pub async fn verify_email(idata: JsonBody<TokenInData>) -> EmptyResult {
+    if !verify_email_token(&idata.token).await? {
+        return Err(AppError::business(ErrorCode::InvalidLink, "The verification link is invalid or has expired."));
+    }
+    empty_ok()
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct ForgotPasswordInData {
+    pub email: String,
+}
+
+/// Always succeeds, so the response doesn't reveal whether the email is registered.
+#[endpoint(tags("account"))]
+pub async fn forgot_password(idata: JsonBody<ForgotPasswordInData>) -> EmptyResult {
+    let mail_config = &config::get().mail;
+    let Some(user) = Users::find_active()
+        .filter(users::Column::Email.eq(idata.into_inner().email))
+        .one(db::pool())
+        .await?
+    else {
+        return empty_ok();
+    };
+    let token = one_time_token::issue(
+        &user.id,
+        one_time_token::PURPOSE_PASSWORD_RESET,
+        mail_config.password_reset_expiry,
+    )
+    .await?;
+    let link = format!("{}/reset-password?token={}", mail_config.link_base_url, token);
+    let tr = Tr(Locale::saved_or_default(user.locale.as_deref()));
+    let html = ResetPasswordMail {
+        email: &user.email,
+        link: &link,
+        expires_in_minutes: mail_config.password_reset_expiry / 60,
+        tr,
+    }
+    .render()
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    mailer::send_later(Mail {
+        to: user.email,
+        subject: tr.t("Reset your password").into_owned(),
+        html,
+    });
+    empty_ok()
+}
+
+#[derive(Deserialize, Validate, ToSchema, Debug)]
+pub struct ResetPasswordInData {
+    pub token: String,
+    #[validate(length(min = 6, message = "password length must be greater than 5"))]
+    pub password: String,
+}
+
+#[endpoint(tags("account"))]
+pub async fn reset_password(idata: JsonBody<ResetPasswordInData>) -> EmptyResult {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let Some(user_id) =
+        one_time_token::consume(&idata.token, one_time_token::PURPOSE_PASSWORD_RESET).await?
+    else {
+        return Err(AppError::business(ErrorCode::InvalidLink, "The reset link is invalid or has expired."));
+    };
+    let conn = db::pool();
+    let Some(user) = Users::find_active_by_id(user_id).one(conn).await? else {
+        return Err(AppError::business(ErrorCode::InvalidLink, "The reset link is invalid or has expired."));
+    };
+    let now = utils::now_primitive();
+    let email_verified = user.email_verified_at.is_some();
+    let mut user: users::ActiveModel = user.into();
+    user.password = Set(utils::hash_password(&idata.password)?);
+    user.password_set = Set(true);
+    // Receiving the mail proves ownership of the address as well.
+    if !email_verified {
+        user.email_verified_at = Set(Some(now));
+    }
+    user.failed_login_count = Set(0);
+    user.locked_until = Set(None);
+    user.updated_at = Set(now);
+    let user = user.update(conn).await?;
+    // Whoever knew the old password may still be signed in.
+    session::revoke(&user.id, None).await?;
+    empty_ok()
+}
+
+#[handler]
+pub async fn verify_email_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    #[derive(Template)]
+    #[template(path = "verify_email.html")]
+    struct VerifyEmailTemplate {
+        verified: bool,
+        tr: Tr,
+    }
+    let verified = match req.query::<String>("token") {
+        Some(token) => verify_email_token(&token).await?,
+        None => false,
+    };
+    res.render(Text::Html(VerifyEmailTemplate { verified, tr: Tr(Locale::of(req)) }.render().unwrap()));
+    Ok(())
+}
+
+#[handler]
+pub async fn reset_password_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    #[derive(Template)]
+    #[template(path = "reset_password.html")]
+    struct ResetPasswordTemplate {
+        token: String,
+        tr: Tr,
+    }
+    let token = req.query::<String>("token").unwrap_or_default();
+    res.render(Text::Html(ResetPasswordTemplate { token, tr: Tr(Locale::of(req)) }.render().unwrap()));
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct LocaleInData {
+    /// `en` or `zh-CN`; `null` goes back to following `Accept-Language`.
+    pub locale: Option<String>,
+}
+
+/// Saves the caller's language for messages and pages, on this browser and at
+/// every later sign-in.
+#[endpoint(tags("account"), security(("bearer" = [])))]
+pub async fn update_locale(
+    idata: JsonBody<LocaleInData>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> EmptyResult {
+    let locale = match idata.into_inner().locale {
+        Some(tag) => Some(Locale::parse(&tag).ok_or_else(|| {
+            AppError::public(Message::new("Unsupported locale {locale}.").arg("locale", tag))
+        })?),
+        None => None,
+    };
+    let user_id = jwt::current_claims(depot)?.user_id();
+    let conn = db::pool();
+    let Some(user) = Users::find_active_by_id(user_id).one(conn).await? else {
+        return Err(StatusError::unauthorized().into());
+    };
+    let mut user: users::ActiveModel = user.into();
+    user.locale = Set(locale.map(|locale| locale.tag().to_owned()));
+    user.updated_at = Set(utils::now_primitive());
+    user.update(conn).await?;
+    match locale {
+        Some(locale) => res.add_cookie(i18n::locale_cookie(locale)),
+        None => res.add_cookie(config::get().cookie.expired(i18n::LOCALE_COOKIE)),
+    };
+    empty_ok()
+}
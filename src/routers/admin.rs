@@ -0,0 +1,273 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::audit::{self, Event};
+use crate::entities::{audit_events, prelude::*, users};
+use crate::hoops::jwt;
+use crate::models::SafeUser;
+use crate::error::ErrorCode;
+use crate::{db, json_ok, metrics, scopes, utils, AppError, JsonResult, Paginated};
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImpersonationOutData {
+    pub user_id: String,
+    pub email: String,
+    pub token: String,
+    pub exp: i64,
+}
+
+/// Mints a short-lived token that acts as the user, for support staff to see
+/// what the customer sees. Every request made with it is audited.
+#[endpoint(
+    tags("admin"),
+    parameters(("user_id", description = "user to impersonate")),
+    security(("bearer" = ["users:impersonate"]))
+)]
+pub async fn impersonate_user(
+    user_id: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<ImpersonationOutData> {
+    let claims = jwt::current_claims(depot)?;
+    if claims.is_api_key() || claims.is_impersonated() {
+        return Err(StatusError::forbidden()
+            .brief("Impersonation needs an interactive admin session.")
+            .into());
+    }
+    let admin_id = claims.user_id().to_owned();
+    let Some(user) = Users::find_active_by_id(user_id.into_inner()).one(db::pool()).await? else {
+        return Err(ErrorCode::UserNotFound.into());
+    };
+    if user.is_admin {
+        return Err(StatusError::forbidden()
+            .brief("Admin accounts cannot be impersonated.")
+            .into());
+    }
+
+    let (token, exp) = jwt::get_impersonation_token(&user.id, &admin_id, scopes::for_user(&user))?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_IMPERSONATION_START,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            diff: Some(serde_json::json!({ "exp": exp })),
+            ..Default::default()
+        },
+    )
+    .await;
+    tracing::warn!(admin_id, user_id = %user.id, "admin started impersonating a user");
+    json_ok(ImpersonationOutData {
+        user_id: user.id,
+        email: user.email,
+        token,
+        exp,
+    })
+}
+
+/// Undoes a soft delete. Sessions and API keys revoked by the deletion stay revoked.
+#[endpoint(
+    tags("admin"),
+    parameters(("user_id", description = "deleted user to restore")),
+    security(("bearer" = ["users:restore"]))
+)]
+pub async fn restore_user(
+    user_id: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<SafeUser> {
+    let conn = db::pool();
+    let Some(user) = Users::find_by_id(user_id.into_inner())
+        .filter(users::Column::DeletedAt.is_not_null())
+        .one(conn)
+        .await?
+    else {
+        return Err(AppError::business(ErrorCode::UserNotFound, "Deleted user does not exist."));
+    };
+    let mut user: users::ActiveModel = user.into();
+    user.deleted_at = Set(None);
+    user.updated_at = Set(utils::now_primitive());
+    let user = SafeUser::from(user.update(conn).await?);
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_USER_RESTORE,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            ..Default::default()
+        },
+    )
+    .await;
+    json_ok(user)
+}
+
+#[derive(Deserialize, Validate, ToSchema, Debug)]
+pub struct VipInData {
+    pub is_vip: bool,
+    #[validate(range(min = 0, message = "vip_level must not be negative"))]
+    pub vip_level: i32,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    pub vip_start_time: Option<time::PrimitiveDateTime>,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    pub vip_end_time: Option<time::PrimitiveDateTime>,
+}
+
+/// Sets a user's VIP status, level and window.
+#[endpoint(
+    tags("admin"),
+    parameters(("user_id", description = "user whose VIP status changes")),
+    security(("bearer" = ["vip:grant"]), ("api_key" = ["vip:grant"]))
+)]
+pub async fn update_vip(
+    user_id: PathParam<String>,
+    idata: JsonBody<VipInData>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<SafeUser> {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    if let (Some(start), Some(end)) = (idata.vip_start_time, idata.vip_end_time)
+        && end <= start
+    {
+        return Err(ErrorCode::InvalidVipWindow.into());
+    }
+    if idata.is_vip && idata.vip_end_time.is_some_and(|end| end <= utils::now_primitive()) {
+        return Err(AppError::business(ErrorCode::VipExpired, "vip_end_time is already in the past."));
+    }
+    let conn = db::pool();
+    let Some(user) = Users::find_active_by_id(user_id.into_inner()).one(conn).await? else {
+        return Err(ErrorCode::UserNotFound.into());
+    };
+    let before = SafeUser::from(user.clone());
+    let mut user: users::ActiveModel = user.into();
+    user.is_vip = Set(idata.is_vip);
+    user.vip_level = Set(idata.vip_level);
+    user.vip_start_time = Set(idata.vip_start_time);
+    user.vip_end_time = Set(idata.vip_end_time);
+    user.updated_at = Set(utils::now_primitive());
+    let user = SafeUser::from(user.update(conn).await?);
+
+    if user.is_vip && !before.is_vip {
+        metrics::VIP_GRANTS_TOTAL.inc();
+    }
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_VIP_UPDATE,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            diff: Some(audit::diff(Some(&before), Some(&user))),
+            ..Default::default()
+        },
+    )
+    .await;
+    json_ok(user)
+}
+
+#[derive(Deserialize, Validate, Extractible, ToSchema, Debug)]
+#[salvo(extract(default_source(from = "query")))]
+pub struct AuditQuery {
+    pub actor_id: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// Inclusive lower bound on `created_at`, ISO 8601.
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    pub since: Option<time::PrimitiveDateTime>,
+    /// Exclusive upper bound on `created_at`, ISO 8601.
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    pub until: Option<time::PrimitiveDateTime>,
+    #[serde(default = "default_page")]
+    #[validate(range(min = 1, max = 1000000, message = "current_page must be between 1 and 1000000"))]
+    pub current_page: u64,
+    #[serde(default = "default_page_size")]
+    #[validate(range(min = 1, max = 100, message = "page_size must be between 1 and 100"))]
+    pub page_size: u64,
+}
+
+fn default_page() -> u64 {
+    1
+}
+
+fn default_page_size() -> u64 {
+    20
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct AuditEventOutData {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub impersonator_id: Option<String>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub diff: Option<serde_json::Value>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub created_at: time::PrimitiveDateTime,
+}
+
+impl From<audit_events::Model> for AuditEventOutData {
+    fn from(model: audit_events::Model) -> Self {
+        Self {
+            id: model.id,
+            actor_id: model.actor_id,
+            impersonator_id: model.impersonator_id,
+            action: model.action,
+            target_type: model.target_type,
+            target_id: model.target_id,
+            diff: model.diff,
+            ip: model.ip,
+            request_id: model.request_id,
+            created_at: model.created_at,
+        }
+    }
+}
+
+/// The audit log, newest first.
+#[endpoint(tags("admin"), security(("bearer" = ["audit:read"]), ("api_key" = ["audit:read"])))]
+pub async fn list_audit_events(req: &mut Request) -> JsonResult<Paginated<AuditEventOutData>> {
+    let query: AuditQuery = req.extract().await?;
+    query.validate()?;
+    let mut select = AuditEvents::find();
+    if let Some(actor_id) = &query.actor_id {
+        select = select.filter(audit_events::Column::ActorId.eq(actor_id));
+    }
+    if let Some(action) = &query.action {
+        select = select.filter(audit_events::Column::Action.eq(action));
+    }
+    if let Some(target_type) = &query.target_type {
+        select = select.filter(audit_events::Column::TargetType.eq(target_type));
+    }
+    if let Some(target_id) = &query.target_id {
+        select = select.filter(audit_events::Column::TargetId.eq(target_id));
+    }
+    if let Some(since) = query.since {
+        select = select.filter(audit_events::Column::CreatedAt.gte(since));
+    }
+    if let Some(until) = query.until {
+        select = select.filter(audit_events::Column::CreatedAt.lt(until));
+    }
+    let paginator = select
+        .order_by_desc(audit_events::Column::CreatedAt)
+        .order_by_desc(audit_events::Column::Id)
+        .paginate(db::pool(), query.page_size);
+    let total = paginator.num_items().await?;
+    let events = paginator.fetch_page(query.current_page - 1).await?;
+    json_ok(Paginated {
+        data: events.into_iter().map(Into::into).collect(),
+        total,
+        current_page: query.current_page,
+        page_size: query.page_size,
+        next_cursor: None,
+    })
+}
@@ -0,0 +1,146 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use validator::Validate;
+
+use crate::entities::{api_keys, prelude::ApiKeys};
+use crate::error::ErrorCode;
+use crate::hoops::jwt::{self, JwtClaims};
+use crate::i18n::Message;
+use crate::utils::{api_key, one_time_token};
+use crate::{db, empty_ok, json_ok, scopes, utils, AppError, AppResult, EmptyResult, JsonResult};
+
+#[derive(Deserialize, Validate, ToSchema, Debug)]
+pub struct CreateApiKeyInData {
+    #[validate(length(min = 1, max = 64, message = "name must be 1 to 64 characters"))]
+    pub name: String,
+    /// At least one of `users:read`, `users:write`, `vip:grant`.
+    pub scopes: Vec<String>,
+    /// Never expires when omitted.
+    #[validate(range(min = 1, max = 3650, message = "expires_in_days must be between 1 and 3650"))]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ApiKeyOutData {
+    pub id: String,
+    pub name: String,
+    /// The first part of the key, enough to recognise it.
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    #[serde(serialize_with = "crate::models::serialize_optional_primitive_datetime")]
+    pub expires_at: Option<time::PrimitiveDateTime>,
+    #[serde(serialize_with = "crate::models::serialize_optional_primitive_datetime")]
+    pub last_used_at: Option<time::PrimitiveDateTime>,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub created_at: time::PrimitiveDateTime,
+}
+
+impl From<api_keys::Model> for ApiKeyOutData {
+    fn from(model: api_keys::Model) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            prefix: model.prefix,
+            scopes: model.scopes.split_whitespace().map(str::to_owned).collect(),
+            expires_at: model.expires_at,
+            last_used_at: model.last_used_at,
+            created_at: model.created_at,
+        }
+    }
+}
+
+/// The only response that contains the full key; it cannot be shown again.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct CreatedApiKeyOutData {
+    pub api_key: ApiKeyOutData,
+    pub key: String,
+}
+
+/// API keys may call the API but not mint or revoke other keys.
+fn session_claims(depot: &Depot) -> AppResult<&JwtClaims> {
+    let claims = jwt::current_claims(depot)?;
+    if claims.is_api_key() {
+        return Err(StatusError::forbidden()
+            .brief("API keys cannot manage API keys.")
+            .into());
+    }
+    Ok(claims)
+}
+
+#[endpoint(tags("api_keys"), security(("bearer" = [])))]
+pub async fn create_api_key(
+    idata: JsonBody<CreateApiKeyInData>,
+    depot: &mut Depot,
+) -> JsonResult<CreatedApiKeyOutData> {
+    let claims = session_claims(depot)?;
+    let idata = idata.into_inner();
+    idata.validate()?;
+    if idata.scopes.is_empty() {
+        return Err(AppError::public("At least one scope is required."));
+    }
+    if let Some(scope) = idata.scopes.iter().find(|scope| !scopes::is_known(scope)) {
+        return Err(AppError::public(Message::new("Unknown scope {scope}.").arg("scope", scope)));
+    }
+    // A key can't do more than the user who creates it.
+    if let Some(scope) = idata.scopes.iter().find(|scope| !claims.has_scope(scope)) {
+        return Err(AppError::business(
+            ErrorCode::ScopeMissing,
+            Message::new("You don't have the {scope} scope.").arg("scope", scope),
+        ));
+    }
+    let user_id = claims.user_id().to_owned();
+
+    let now = utils::now_primitive();
+    let (prefix, key) = api_key::generate();
+    let model = api_keys::ActiveModel {
+        id: Set(Ulid::new().to_string()),
+        user_id: Set(user_id),
+        name: Set(idata.name),
+        prefix: Set(prefix),
+        secret_hash: Set(one_time_token::hash_token(&key)),
+        scopes: Set(idata.scopes.join(" ")),
+        expires_at: Set(idata
+            .expires_in_days
+            .map(|days| now + time::Duration::days(days))),
+        last_used_at: Set(None),
+        revoked_at: Set(None),
+        created_at: Set(now),
+    };
+    let model = ApiKeys::insert(model).exec_with_returning(db::pool()).await?;
+    json_ok(CreatedApiKeyOutData {
+        api_key: model.into(),
+        key,
+    })
+}
+
+#[endpoint(tags("api_keys"), security(("bearer" = [])))]
+pub async fn list_api_keys(depot: &mut Depot) -> JsonResult<Vec<ApiKeyOutData>> {
+    let user_id = session_claims(depot)?.user_id();
+    let keys = ApiKeys::find()
+        .filter(api_keys::Column::UserId.eq(user_id))
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .order_by_desc(api_keys::Column::CreatedAt)
+        .all(db::pool())
+        .await?;
+    json_ok(keys.into_iter().map(Into::into).collect())
+}
+
+#[endpoint(tags("api_keys"), security(("bearer" = [])))]
+pub async fn revoke_api_key(key_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let user_id = session_claims(depot)?.user_id();
+    let result = ApiKeys::update_many()
+        .col_expr(api_keys::Column::RevokedAt, Expr::value(utils::now_primitive()))
+        .filter(api_keys::Column::Id.eq(key_id.into_inner()))
+        .filter(api_keys::Column::UserId.eq(user_id))
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .exec(db::pool())
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(StatusError::not_found().brief("API key not found.").into());
+    }
+    empty_ok()
+}
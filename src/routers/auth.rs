@@ -1,27 +1,44 @@
-use cookie::Cookie;
 use rinja::Template;
 use salvo::oapi::extract::*;
 use salvo::prelude::*;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, UpdateMany, Value};
 use serde::{Deserialize, Serialize};
 
 use crate::entities::{prelude::Users, users};
-use crate::hoops::jwt;
-use crate::{db, json_ok, utils, AppResult, JsonResult};
+use crate::audit::{self, Event};
+use crate::config::RateLimitConfig;
+use crate::hoops::{csrf, jwt};
+use crate::i18n::{self, Locale, Tr};
+use crate::utils::{one_time_token, session};
+use crate::error::ErrorCode;
+use crate::{config, db, json_ok, metrics, scopes, utils, AppError, AppResult, JsonResult};
 
 #[handler]
-pub async fn login_page(res: &mut Response) -> AppResult<()> {
+pub async fn login_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
     #[derive(Template)]
     #[template(path = "login.html")]
-    struct LoginTemplate {}
-    if let Some(cookie) = res.cookies().get("jwt_token") {
+    struct LoginTemplate {
+        providers: Vec<String>,
+        tr: Tr,
+    }
+    if let Some(cookie) = res.cookies().get(jwt::TOKEN_COOKIE) {
         let token = cookie.value().to_string();
         if jwt::decode_token(&token) {
             res.render(Redirect::other("/users"));
             return Ok(());
         }
     }
-    let hello_tmpl = LoginTemplate {};
+    let providers = config::get()
+        .oidc
+        .providers
+        .iter()
+        .map(|provider| provider.name.clone())
+        .collect();
+    let hello_tmpl = LoginTemplate {
+        providers,
+        tr: Tr(Locale::of(req)),
+    };
     res.render(Text::Html(hello_tmpl.render().unwrap()));
     Ok(())
 }
@@ -50,31 +67,104 @@ pub struct LoginOutData {
     pub exp: i64,
 }
 
+/// Returned instead of the session token when the account has two-factor enabled;
+/// exchange `mfa_token` and a code at `POST /api/login/mfa`.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct MfaRequiredOutData {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub exp: i64,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(untagged)]
+pub enum LoginResult {
+    LoggedIn(LoginOutData),
+    MfaRequired(MfaRequiredOutData),
+}
+
 #[endpoint(tags("auth"))]
 pub async fn post_login(
     idata: JsonBody<LoginInData>,
+    req: &mut Request,
+    depot: &mut Depot,
     res: &mut Response,
-) -> JsonResult<LoginOutData> {
+) -> JsonResult<LoginResult> {
     let idata = idata.into_inner();
     let conn = db::pool();
-    let Some(user) = Users::find()
+    let Some(user) = Users::find_active()
         .filter(users::Column::Email.eq(idata.email))
         .one(conn)
         .await?
     else {
-        return Err(StatusError::unauthorized()
-            .brief("User does not exist.")
-            .into());
+        // Spend the same time as a real verification so response timing
+        // doesn't reveal which emails are registered.
+        utils::verify_dummy_password(&idata.password);
+        return Err(login_failed());
     };
 
-    if utils::verify_password(&idata.password, &user.password).is_err()
-    {
-        return Err(StatusError::unauthorized()
-            .brief("Account not exist or password is incorrect.")
-            .into());
+    let now = utils::now_primitive();
+    let password_ok = utils::verify_password(&idata.password, &user.password).is_ok();
+    if user.locked_until.is_some_and(|until| until > now) {
+        return Err(login_failed());
+    }
+    if !password_ok {
+        record_failed_login(&user, now, req, depot).await?;
+        return Err(login_failed());
+    }
+    if user.failed_login_count > 0 || user.locked_until.is_some() {
+        Users::update_many()
+            .col_expr(users::Column::FailedLoginCount, Expr::value(0))
+            .col_expr(users::Column::LockedUntil, Expr::value(Value::TimeDateTime(None)))
+            .filter(users::Column::Id.eq(&user.id))
+            .exec(conn)
+            .await?;
+    }
+
+    if config::get().mail.require_verified_email && user.email_verified_at.is_none() {
+        return Err(ErrorCode::EmailNotVerified.into());
+    }
+    if user.totp_enabled {
+        let jti = one_time_token::issue(
+            &user.id,
+            one_time_token::PURPOSE_MFA_PENDING,
+            config::get().mfa.pending_expiry,
+        )
+        .await?;
+        let (mfa_token, exp) = jwt::get_mfa_pending_token(&user.id, jti)?;
+        return json_ok(LoginResult::MfaRequired(MfaRequiredOutData {
+            mfa_required: true,
+            mfa_token,
+            exp,
+        }));
     }
+    json_ok(LoginResult::LoggedIn(complete_login(user, req, depot, res).await?))
+}
 
-    let (token, exp) = jwt::get_token(&user.id)?;
+/// Records the session and issues its token, `jwt_token` cookie and CSRF token
+/// once every login factor has been checked.
+pub async fn complete_login(
+    user: users::Model,
+    req: &Request,
+    depot: &Depot,
+    res: &mut Response,
+) -> AppResult<LoginOutData> {
+    metrics::LOGINS_TOTAL.inc();
+    let sid = session::create(&user.id, req, config::get().jwt.expiry).await?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_LOGIN,
+            actor_id: Some(&user.id),
+            target_type: Some("session"),
+            target_id: Some(&sid),
+            diff: None,
+        },
+    )
+    .await;
+    let (token, exp) = jwt::get_token(&user.id, sid, scopes::for_user(&user))?;
+    let user_locale = user.locale.as_deref().and_then(Locale::parse);
     let odata = LoginOutData {
         id: user.id,
         email: user.email,
@@ -87,10 +177,104 @@ pub async fn post_login(
         token,
         exp,
     };
-    let cookie = Cookie::build(("jwt_token", odata.token.clone()))
-        .path("/")
+    let max_age = cookie::time::Duration::seconds(exp - time::OffsetDateTime::now_utc().unix_timestamp());
+    let cookie = config::get()
+        .cookie
+        .build(jwt::TOKEN_COOKIE, odata.token.clone())
         .http_only(true)
+        .max_age(max_age)
         .build();
     res.add_cookie(cookie);
-    json_ok(odata)
+    res.add_cookie(csrf::csrf_cookie(max_age));
+    if let Some(locale) = user_locale {
+        res.add_cookie(i18n::locale_cookie(locale));
+    }
+    Ok(odata)
+}
+
+/// The same error whether the account is missing, locked or the password is wrong.
+pub fn login_failed() -> AppError {
+    metrics::LOGIN_FAILURES_TOTAL.inc();
+    ErrorCode::InvalidCredentials.into()
+}
+
+/// Locks the account and resets its count once `max_failed_logins` is reached.
+fn lock_at_limit(
+    user_id: &str,
+    now: time::PrimitiveDateTime,
+    config: &RateLimitConfig,
+) -> UpdateMany<users::Entity> {
+    Users::update_many()
+        .col_expr(users::Column::FailedLoginCount, Expr::value(0))
+        .col_expr(
+            users::Column::LockedUntil,
+            Expr::value(now + time::Duration::seconds(config.lockout_seconds)),
+        )
+        .filter(users::Column::Id.eq(user_id))
+        .filter(users::Column::FailedLoginCount.gte(config.max_failed_logins))
+}
+
+/// Counts a wrong password and locks the account once the configured limit is reached.
+///
+/// Both steps are decided by the database rather than from `user`, which may
+/// be stale when attempts race: every failure is counted, and whichever one
+/// finds the count at the limit locks the account and resets it.
+pub async fn record_failed_login(
+    user: &users::Model,
+    now: time::PrimitiveDateTime,
+    req: &Request,
+    depot: &Depot,
+) -> AppResult<()> {
+    let config = &config::get().rate_limit;
+    let conn = db::pool();
+    Users::update_many()
+        .col_expr(
+            users::Column::FailedLoginCount,
+            Expr::col(users::Column::FailedLoginCount).add(1),
+        )
+        .filter(users::Column::Id.eq(&user.id))
+        .exec(conn)
+        .await?;
+    let locked = lock_at_limit(&user.id, now, config).exec(conn).await?.rows_affected == 1;
+    if locked {
+        tracing::warn!(user_id = %user.id, "account locked after repeated login failures");
+    }
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_LOGIN_FAILED,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            diff: Some(serde_json::json!({ "locked": locked })),
+            ..Default::default()
+        },
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{DbBackend, QueryTrait};
+
+    use super::*;
+
+    #[test]
+    fn locks_once_the_failure_limit_is_reached() {
+        let config = RateLimitConfig {
+            max_failed_logins: 5,
+            lockout_seconds: 900,
+            ..Default::default()
+        };
+        let now = time::PrimitiveDateTime::new(
+            time::Date::from_calendar_date(2026, time::Month::October, 19).unwrap(),
+            time::Time::from_hms(12, 0, 0).unwrap(),
+        );
+        let sql = lock_at_limit("u1", now, &config).build(DbBackend::MySql).to_string();
+        assert!(sql.contains("`failed_login_count` = 0"), "{sql}");
+        assert!(sql.contains("`locked_until` = '2026-10-19 12:15:00"), "{sql}");
+        assert!(sql.contains("`id` = 'u1'"), "{sql}");
+        assert!(sql.contains("`failed_login_count` >= 5"), "{sql}");
+    }
 }
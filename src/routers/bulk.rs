@@ -0,0 +1,416 @@
+use std::collections::HashSet;
+
+use argon2::PasswordHash;
+use futures_util::StreamExt;
+use salvo::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use salvo::http::HeaderValue;
+use salvo::prelude::*;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect, Set, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use validator::{Validate, ValidateEmail};
+
+use super::user::{CreateInData, UserFilter};
+use crate::audit::{self, Event};
+use crate::entities::{prelude::Users, users};
+use crate::i18n::Message;
+use crate::models::SafeUser;
+use crate::{db, json_ok, metrics, utils, AppError, AppResult, JsonResult};
+
+/// Largest import body accepted, roughly a hundred thousand rows.
+const MAX_IMPORT_BYTES: usize = 16 * 1024 * 1024;
+/// Rows per `INSERT` statement.
+const INSERT_BATCH_SIZE: usize = 500;
+
+const FORMAT_CSV: &str = "csv";
+const FORMAT_NDJSON: &str = "ndjson";
+
+/// One user to import. Give either `password` or an argon2 `password_hash`
+/// carried over from the old system.
+#[derive(Deserialize, Debug)]
+struct ImportRow {
+    email: String,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    password_hash: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    is_vip: Option<bool>,
+    #[serde(default)]
+    vip_level: Option<i32>,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    vip_start_time: Option<time::PrimitiveDateTime>,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    vip_end_time: Option<time::PrimitiveDateTime>,
+}
+
+#[derive(Deserialize, Extractible, ToSchema, Debug)]
+#[salvo(extract(default_source(from = "query")))]
+pub struct ImportQuery {
+    /// Only validate and report, don't insert anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportRowError {
+    /// Line in the uploaded file, starting at 1.
+    pub line: usize,
+    pub email: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportReportOutData {
+    pub dry_run: bool,
+    pub total: usize,
+    pub imported: usize,
+    /// Nothing is imported while this is not empty.
+    pub errors: Vec<ImportRowError>,
+}
+
+/// Parses the body as CSV (with a header row) or NDJSON, following the
+/// `Content-Type`. Rows that can't be parsed become errors right away.
+fn parse_rows(format: &str, body: &[u8]) -> (Vec<(usize, ImportRow)>, Vec<ImportRowError>) {
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    if format == FORMAT_CSV {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+        for (index, record) in reader.deserialize::<ImportRow>().enumerate() {
+            // Line 1 is the header.
+            let line = index + 2;
+            match record {
+                Ok(row) => rows.push((line, row)),
+                Err(e) => errors.push(ImportRowError {
+                    line,
+                    email: None,
+                    message: e.to_string(),
+                }),
+            }
+        }
+    } else {
+        for (index, text) in body.split(|b| *b == b'\n').enumerate() {
+            if text.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match serde_json::from_slice::<ImportRow>(text) {
+                Ok(row) => rows.push((index + 1, row)),
+                Err(e) => errors.push(ImportRowError {
+                    line: index + 1,
+                    email: None,
+                    message: e.to_string(),
+                }),
+            }
+        }
+    }
+    (rows, errors)
+}
+
+/// Applies the `POST /api/users` rules to a row, or the hash format check when
+/// it carries a password hash instead.
+fn validate_row(row: &ImportRow) -> Result<(), String> {
+    match (&row.password, &row.password_hash) {
+        (Some(password), None) => CreateInData {
+            email: row.email.clone(),
+            password: password.clone(),
+        }
+        .validate()
+        .map_err(|e| e.to_string())?,
+        (None, Some(hash)) => {
+            if !row.email.validate_email() {
+                return Err("Please enter a valid email address".into());
+            }
+            let parsed = PasswordHash::new(hash).map_err(|e| format!("invalid password_hash: {e}"))?;
+            if !parsed.algorithm.as_str().starts_with("argon2") {
+                return Err("password_hash must be an argon2 hash".into());
+            }
+        }
+        _ => return Err("give exactly one of password and password_hash".into()),
+    }
+    if row.vip_level.is_some_and(|level| level < 0) {
+        return Err("vip_level must not be negative".into());
+    }
+    if let (Some(start), Some(end)) = (row.vip_start_time, row.vip_end_time)
+        && end <= start
+    {
+        return Err("vip_end_time must be after vip_start_time".into());
+    }
+    Ok(())
+}
+
+/// Lowercased emails among `emails` that already belong to an account, deleted or not.
+async fn taken_emails(emails: &[String]) -> AppResult<HashSet<String>> {
+    let mut taken = HashSet::new();
+    for chunk in emails.chunks(INSERT_BATCH_SIZE) {
+        let found: Vec<String> = Users::find()
+            .select_only()
+            .column(users::Column::Email)
+            .filter(users::Column::Email.is_in(chunk.to_vec()))
+            .into_tuple()
+            .all(db::pool())
+            .await?;
+        taken.extend(found.into_iter().map(|email| email.to_lowercase()));
+    }
+    Ok(taken)
+}
+
+/// Creates users from a CSV or NDJSON upload (`Content-Type: text/csv` or
+/// `application/x-ndjson`).
+///
+/// Every row is validated first and all errors are reported together. The
+/// rows are only inserted, in one transaction, when there are none and
+/// `dry_run` is off.
+#[endpoint(
+    tags("admin"),
+    request_body(content = String, content_type = "text/csv"),
+    security(("bearer" = ["users:import"]), ("api_key" = ["users:import"]))
+)]
+pub async fn import_users(req: &mut Request, depot: &mut Depot) -> JsonResult<ImportReportOutData> {
+    let query: ImportQuery = req.extract().await?;
+    let format = match req.content_type() {
+        Some(mime) if mime.subtype() == FORMAT_CSV => FORMAT_CSV,
+        Some(mime) if ["x-ndjson", "ndjson", "jsonl"].contains(&mime.subtype().as_str()) => {
+            FORMAT_NDJSON
+        }
+        _ => {
+            return Err(AppError::public(
+                "Upload text/csv or application/x-ndjson.",
+            ));
+        }
+    };
+    let body = req.payload_with_max_size(MAX_IMPORT_BYTES).await?.clone();
+    let (rows, mut errors) = parse_rows(format, &body);
+    let total = rows.len() + errors.len();
+
+    let mut seen = HashSet::new();
+    let mut valid = Vec::with_capacity(rows.len());
+    for (line, row) in rows {
+        let result = validate_row(&row).and_then(|_| {
+            if seen.insert(row.email.to_lowercase()) {
+                Ok(())
+            } else {
+                Err("email appears more than once in the file".to_owned())
+            }
+        });
+        match result {
+            Ok(()) => valid.push((line, row)),
+            Err(message) => errors.push(ImportRowError {
+                line,
+                email: Some(row.email),
+                message,
+            }),
+        }
+    }
+    let emails: Vec<String> = valid.iter().map(|(_, row)| row.email.clone()).collect();
+    let taken = taken_emails(&emails).await?;
+    valid.retain(|(line, row)| {
+        if !taken.contains(&row.email.to_lowercase()) {
+            return true;
+        }
+        errors.push(ImportRowError {
+            line: *line,
+            email: Some(row.email.clone()),
+            message: "email is already registered".into(),
+        });
+        false
+    });
+    errors.sort_by_key(|error| error.line);
+
+    if query.dry_run || !errors.is_empty() {
+        return json_ok(ImportReportOutData {
+            dry_run: query.dry_run,
+            total,
+            imported: 0,
+            errors,
+        });
+    }
+
+    let vip_grants = valid.iter().filter(|(_, row)| row.is_vip == Some(true)).count();
+    // Hashing is slow on purpose; keep it off the async workers.
+    let models = tokio::task::spawn_blocking(move || -> AppResult<Vec<users::ActiveModel>> {
+        let now = utils::now_primitive();
+        valid
+            .into_iter()
+            .map(|(_, row)| {
+                let password = match row.password_hash {
+                    Some(hash) => hash,
+                    None => utils::hash_password(row.password.as_deref().unwrap_or_default())?,
+                };
+                Ok(users::ActiveModel {
+                    id: Set(Ulid::new().to_string()),
+                    email: Set(row.email),
+                    password: Set(password),
+                    password_set: Set(true),
+                    is_vip: Set(row.is_vip.unwrap_or(false)),
+                    vip_start_time: Set(row.vip_start_time),
+                    vip_end_time: Set(row.vip_end_time),
+                    vip_level: Set(row.vip_level.unwrap_or(0)),
+                    created_at: Set(now),
+                    updated_at: Set(now),
+                    failed_login_count: Set(0),
+                    locked_until: Set(None),
+                    totp_secret: Set(None),
+                    totp_enabled: Set(false),
+                    totp_last_step: Set(None),
+                    email_verified_at: Set(row.email_verified.unwrap_or(false).then_some(now)),
+                    is_admin: Set(false),
+                    deleted_at: Set(None),
+                    deletion_scheduled_at: Set(None),
+                    anonymized_at: Set(None),
+                    locale: Set(None),
+                })
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| AppError::internal(e.to_string()))??;
+
+    let imported = models.len();
+    let txn = db::pool().begin().await?;
+    for batch in models.chunks(INSERT_BATCH_SIZE) {
+        Users::insert_many(batch.to_vec()).exec(&txn).await?;
+    }
+    txn.commit().await?;
+    metrics::VIP_GRANTS_TOTAL.inc_by(vip_grants as u64);
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_USER_IMPORT,
+            target_type: Some("user"),
+            diff: Some(serde_json::json!({ "imported": imported, "format": format })),
+            ..Default::default()
+        },
+    )
+    .await;
+    json_ok(ImportReportOutData {
+        dry_run: false,
+        total,
+        imported,
+        errors,
+    })
+}
+
+#[derive(Deserialize, Extractible, ToSchema, Debug)]
+#[salvo(extract(default_source(from = "query")))]
+pub struct ExportQuery {
+    /// `csv` (default) or `ndjson`.
+    pub format: Option<String>,
+}
+
+/// Header of the CSV export, in the field order of [`SafeUser`].
+const CSV_COLUMNS: [&str; 8] = [
+    "id",
+    "email",
+    "is_vip",
+    "vip_start_time",
+    "vip_end_time",
+    "vip_level",
+    "created_at",
+    "updated_at",
+];
+
+fn csv_line<T: Serialize>(record: &T) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    writer.serialize(record)?;
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// Streams every user matching the `list_users` filters as CSV or NDJSON,
+/// without loading them all at once.
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = ["users:export"]), ("api_key" = ["users:export"]))
+)]
+pub async fn export_users(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let filter: UserFilter = req.extract().await?;
+    filter.validate()?;
+    let query: ExportQuery = req.extract().await?;
+    let format = match query.format.as_deref() {
+        None | Some(FORMAT_CSV) => FORMAT_CSV,
+        Some(FORMAT_NDJSON) => FORMAT_NDJSON,
+        Some(other) => return Err(AppError::public(Message::new("Unknown export format {format}.").arg("format", other))),
+    };
+    let users = filter
+        .apply(Users::find_active())
+        .stream(db::pool())
+        .await?;
+
+    // Written up front, so an export matching nobody still has its header.
+    let header = match format {
+        FORMAT_CSV => Some(csv_line(&CSV_COLUMNS).map_err(|e| AppError::internal(e.to_string()))),
+        _ => None,
+    };
+    let lines = users.map(move |user| -> Result<Vec<u8>, AppError> {
+        let user = SafeUser::from(user?);
+        if format == FORMAT_NDJSON {
+            let mut line = serde_json::to_vec(&user).map_err(|e| AppError::internal(e.to_string()))?;
+            line.push(b'\n');
+            return Ok(line);
+        }
+        csv_line(&user).map_err(|e| AppError::internal(e.to_string()))
+    });
+    let lines = futures_util::stream::iter(header).chain(lines);
+    let (content_type, extension) = if format == FORMAT_CSV {
+        ("text/csv; charset=utf-8", FORMAT_CSV)
+    } else {
+        ("application/x-ndjson", FORMAT_NDJSON)
+    };
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"users.{extension}\"")) {
+        res.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+    res.stream(lines);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_rows_are_parsed_and_validated() {
+        let hash = utils::hash_password("secret1").unwrap();
+        let body = format!(
+            "email,password,password_hash,vip_level\n\
+             a@example.com,secret1,,1\n\
+             b@example.com,,\"{hash}\",\n\
+             not-an-email,secret1,,\n\
+             c@example.com,short,,\n\
+             d@example.com,secret1,\"{hash}\",\n\
+             e@example.com,secret1,,lots\n"
+        );
+        let (rows, errors) = parse_rows(FORMAT_CSV, body.as_bytes());
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), [7]);
+        let results: Vec<_> = rows.iter().map(|(line, row)| (*line, validate_row(row).is_ok())).collect();
+        assert_eq!(results, [(2, true), (3, true), (4, false), (5, false), (6, false)]);
+
+        let body = "{\"email\":\"a@example.com\",\"password\":\"secret1\"}\n\n{\"email\":1}\n";
+        let (rows, errors) = parse_rows(FORMAT_NDJSON, body.as_bytes());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(errors[0].line, 3);
+    }
+
+    #[test]
+    fn csv_header_matches_the_exported_fields() {
+        let now = utils::now_primitive();
+        let user = SafeUser {
+            id: "01J".into(),
+            email: "a@example.com".into(),
+            is_vip: false,
+            vip_start_time: None,
+            vip_end_time: None,
+            vip_level: 0,
+            created_at: now,
+            updated_at: now,
+        };
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&user).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let header = String::from_utf8(csv_line(&CSV_COLUMNS).unwrap()).unwrap();
+        assert_eq!(written.lines().next(), header.lines().next());
+    }
+}
@@ -0,0 +1,132 @@
+use rinja::Template;
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::{ActiveModelTrait, ColumnTrait, QueryFilter, Set};
+use serde::Deserialize;
+
+use super::auth;
+use crate::entities::{prelude::Users, users};
+use crate::hoops::jwt;
+use crate::mailer::{self, Mail};
+use crate::utils::one_time_token;
+use crate::error::ErrorCode;
+use crate::i18n::{Locale, Tr};
+use crate::{config, db, empty_ok, utils, AppError, AppResult, EmptyResult};
+
+#[derive(Template)]
+#[template(path = "emails/magic_link.html")]
+struct MagicLinkMail<'a> {
+    email: &'a str,
+    link: &'a str,
+    expires_in_minutes: i64,
+    tr: Tr,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct MagicLinkInData {
+    pub email: String,
+    /// Where to land after login; must be listed in `magic_link.allowed_redirects`.
+    pub redirect: Option<String>,
+}
+
+/// Mails a single-use login link. Succeeds for unknown emails too, so the
+/// response doesn't reveal which addresses are registered.
+#[endpoint(tags("auth"))]
+pub async fn request_magic_link(idata: JsonBody<MagicLinkInData>) -> EmptyResult {
+    let idata = idata.into_inner();
+    let link_config = &config::get().magic_link;
+    let redirect = idata
+        .redirect
+        .unwrap_or_else(|| link_config.default_redirect.clone());
+    if !link_config.is_allowed_redirect(&redirect) {
+        return Err(AppError::public("Redirect target is not allowed."));
+    }
+    let Some(user) = Users::find_active()
+        .filter(users::Column::Email.eq(idata.email))
+        .one(db::pool())
+        .await?
+    else {
+        return empty_ok();
+    };
+
+    let jti = one_time_token::issue(
+        &user.id,
+        one_time_token::PURPOSE_MAGIC_LINK,
+        link_config.expiry,
+    )
+    .await?;
+    let token = jwt::get_magic_link_token(&user.id, jti, redirect)?;
+    let link = format!(
+        "{}/api/login/magic-link/consume?token={}",
+        config::get().mail.link_base_url,
+        token
+    );
+    let tr = Tr(Locale::saved_or_default(user.locale.as_deref()));
+    let html = MagicLinkMail {
+        email: &user.email,
+        link: &link,
+        expires_in_minutes: link_config.expiry / 60,
+        tr,
+    }
+    .render()
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    mailer::send_later(Mail {
+        to: user.email,
+        subject: tr.t("Your login link").into_owned(),
+        html,
+    });
+    empty_ok()
+}
+
+/// Logs in with an emailed link and redirects to the target it was issued for.
+///
+/// Same-site targets get the `jwt_token` cookie only; absolute deep links also
+/// receive the token in the URL fragment, since they can't read the cookie.
+#[endpoint(tags("auth"))]
+pub async fn consume_magic_link(
+    token: QueryParam<String, true>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> AppResult<()> {
+    let invalid = || AppError::business(ErrorCode::InvalidLink, "The login link is invalid or has expired.");
+    let claims = jwt::decode_magic_link_token(&token).ok_or_else(invalid)?;
+    // Checked again in case the allowlist changed after the link was sent.
+    if !config::get().magic_link.is_allowed_redirect(&claims.redirect) {
+        return Err(invalid());
+    }
+    let user_id = one_time_token::consume(&claims.jti, one_time_token::PURPOSE_MAGIC_LINK)
+        .await?
+        .filter(|user_id| *user_id == claims.link_uid)
+        .ok_or_else(invalid)?;
+    let conn = db::pool();
+    let user = Users::find_active_by_id(user_id).one(conn).await?.ok_or_else(invalid)?;
+
+    let now = utils::now_primitive();
+    if user.locked_until.is_some_and(|until| until > now) {
+        return Err(auth::login_failed());
+    }
+    // The link replaces the password, not the second factor.
+    if user.totp_enabled {
+        return Err(StatusError::forbidden()
+            .brief("Two-factor authentication is enabled, please sign in with your password.")
+            .into());
+    }
+    // Opening the mailed link proves ownership of the address.
+    let user = if user.email_verified_at.is_none() {
+        let mut user: users::ActiveModel = user.into();
+        user.email_verified_at = Set(Some(now));
+        user.update(conn).await?
+    } else {
+        user
+    };
+
+    let odata = auth::complete_login(user, req, depot, res).await?;
+    let target = if claims.redirect.starts_with('/') {
+        claims.redirect
+    } else {
+        format!("{}#token={}&exp={}", claims.redirect, odata.token, odata.exp)
+    };
+    res.render(Redirect::other(target));
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use salvo::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use salvo::prelude::*;
+
+use crate::{config, metrics, utils, AppResult};
+
+#[handler]
+pub async fn scrape(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    if let Some(expected) = &config::get().metrics.bearer_token {
+        let provided = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if !provided.is_some_and(|provided| utils::constant_time_eq(provided, expected)) {
+            return Err(StatusError::unauthorized()
+                .brief("Missing or invalid metrics token.")
+                .into());
+        }
+    }
+    res.add_header(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8", true)?;
+    res.write_body(metrics::render()?)?;
+    Ok(())
+}
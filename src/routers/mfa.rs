@@ -0,0 +1,377 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, EntityTrait, QueryFilter, Set,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+use ulid::Ulid;
+
+use super::auth::{self, LoginOutData};
+use crate::entities::{prelude::*, user_recovery_codes, users};
+use crate::hoops::jwt;
+use crate::utils::one_time_token;
+use crate::error::ErrorCode;
+use crate::{config, db, empty_ok, json_ok, utils, AppError, AppResult, EmptyResult, JsonResult};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// `issuer` only labels the otpauth URI; checking codes works without it.
+fn totp_for(user: &users::Model, secret: &str, issuer: Option<String>) -> AppResult<TOTP> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|e| AppError::internal(format!("invalid totp secret: {e:?}")))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        issuer,
+        user.email.clone(),
+    )
+    .map_err(|e| AppError::internal(format!("invalid totp parameters: {e}")))
+}
+
+/// The time step `code` is valid for at `unix_time`, unless that step is not
+/// newer than `last_step`: each code is accepted once (RFC 6238 §5.2).
+fn totp_step(totp: &TOTP, code: &str, unix_time: u64, last_step: Option<i64>) -> Option<i64> {
+    let current = unix_time / totp.step;
+    let skew = u64::from(totp.skew);
+    (current.saturating_sub(skew)..=current + skew)
+        .map(|step| (step, step as i64))
+        .filter(|(_, step)| last_step.is_none_or(|last| *step > last))
+        .find(|(step, _)| utils::constant_time_eq(&totp.generate(step * totp.step), code.trim()))
+        .map(|(_, step)| step)
+}
+
+/// The step of a TOTP code valid at `unix_time` that the user hasn't used yet.
+fn check_totp(user: &users::Model, code: &str, unix_time: u64) -> AppResult<Option<i64>> {
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return Ok(None);
+    };
+    Ok(totp_step(&totp_for(user, secret, None)?, code, unix_time, user.totp_last_step))
+}
+
+fn unix_now() -> u64 {
+    time::OffsetDateTime::now_utc().unix_timestamp() as u64
+}
+
+/// Accepts a current TOTP code and records its step. The conditional update
+/// lets only one of two requests racing with the same code through.
+async fn use_totp<C: ConnectionTrait>(conn: &C, user: &users::Model, code: &str) -> AppResult<bool> {
+    let Some(step) = check_totp(user, code, unix_now())? else {
+        return Ok(false);
+    };
+    let claimed = Users::update_many()
+        .col_expr(users::Column::TotpLastStep, Expr::value(step))
+        .filter(users::Column::Id.eq(&user.id))
+        .filter(
+            Condition::any()
+                .add(users::Column::TotpLastStep.is_null())
+                .add(users::Column::TotpLastStep.lt(step)),
+        )
+        .exec(conn)
+        .await?
+        .rows_affected
+        == 1;
+    Ok(claimed)
+}
+
+/// A fresh recovery code: ten lowercase letters and digits, as `xxxxx-xxxxx`.
+fn new_recovery_code() -> String {
+    let raw = utils::random_string(10).to_lowercase();
+    format!("{}-{}", &raw[..5], &raw[5..])
+}
+
+/// The candidate matching `code`, ignoring case and surrounding whitespace.
+fn find_recovery_code(
+    candidates: Vec<user_recovery_codes::Model>,
+    code: &str,
+) -> Option<user_recovery_codes::Model> {
+    let code = code.trim().to_lowercase();
+    candidates
+        .into_iter()
+        .find(|candidate| utils::verify_password(&code, &candidate.code_hash).is_ok())
+}
+
+/// Marks the matching unused recovery code as used. Returns whether one matched.
+async fn consume_recovery_code<C: ConnectionTrait>(conn: &C, user_id: &str, code: &str) -> AppResult<bool> {
+    let candidates = UserRecoveryCodes::find()
+        .filter(user_recovery_codes::Column::UserId.eq(user_id))
+        .filter(user_recovery_codes::Column::UsedAt.is_null())
+        .all(conn)
+        .await?;
+    let Some(candidate) = find_recovery_code(candidates, code) else {
+        return Ok(false);
+    };
+    let mut candidate: user_recovery_codes::ActiveModel = candidate.into();
+    candidate.used_at = Set(Some(utils::now_primitive()));
+    candidate.update(conn).await?;
+    Ok(true)
+}
+
+/// Accepts either an unused current TOTP code or an unused recovery code.
+async fn verify_second_factor<C: ConnectionTrait>(
+    conn: &C,
+    user: &users::Model,
+    code: &str,
+) -> AppResult<bool> {
+    Ok(use_totp(conn, user, code).await? || consume_recovery_code(conn, &user.id, code).await?)
+}
+
+/// The signed-in account. API keys can't change the second factor: a leaked
+/// key could otherwise enroll its own secret or switch two-factor off.
+async fn current_user(depot: &Depot) -> AppResult<users::Model> {
+    let claims = jwt::current_claims(depot)?;
+    if claims.is_api_key() {
+        return Err(StatusError::forbidden()
+            .brief("This needs a signed-in session, not an API key.")
+            .into());
+    }
+    Users::find_active_by_id(claims.user_id())
+        .one(db::pool())
+        .await?
+        .ok_or_else(|| StatusError::unauthorized().into())
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct TotpEnrollOutData {
+    /// Base32 secret, for manual entry.
+    pub secret: String,
+    /// `otpauth://` URI to render as a QR code.
+    pub otpauth_uri: String,
+}
+
+/// Starts TOTP enrollment. Two-factor stays off until the first code is confirmed.
+#[endpoint(tags("mfa"), security(("bearer" = [])))]
+pub async fn enroll_totp(depot: &mut Depot) -> JsonResult<TotpEnrollOutData> {
+    let user = current_user(depot).await?;
+    if user.totp_enabled {
+        return Err(AppError::public("Two-factor authentication is already enabled."));
+    }
+    let secret = match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!("to_encoded always returns an encoded secret"),
+    };
+    let otpauth_uri = totp_for(&user, &secret, Some(config::get().mfa.issuer.clone()))?.get_url();
+
+    let mut user: users::ActiveModel = user.into();
+    user.totp_secret = Set(Some(secret.clone()));
+    user.updated_at = Set(utils::now_primitive());
+    user.update(db::pool()).await?;
+    json_ok(TotpEnrollOutData {
+        secret,
+        otpauth_uri,
+    })
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct TotpCodeInData {
+    pub code: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RecoveryCodesOutData {
+    /// Shown only once; each code can replace a TOTP code a single time.
+    pub recovery_codes: Vec<String>,
+}
+
+/// Enables two-factor after checking the first code and hands out recovery codes.
+#[endpoint(tags("mfa"), security(("bearer" = [])))]
+pub async fn confirm_totp(
+    idata: JsonBody<TotpCodeInData>,
+    depot: &mut Depot,
+) -> JsonResult<RecoveryCodesOutData> {
+    let user = current_user(depot).await?;
+    if user.totp_enabled {
+        return Err(AppError::public("Two-factor authentication is already enabled."));
+    }
+    if user.totp_secret.is_none() {
+        return Err(AppError::public("Start enrollment before confirming a code."));
+    }
+    let Some(step) = check_totp(&user, &idata.code, unix_now())? else {
+        return Err(ErrorCode::IncorrectCode.into());
+    };
+
+    let now = utils::now_primitive();
+    let user_id = user.id.clone();
+    let mut recovery_codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    let mut models = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = new_recovery_code();
+        models.push(user_recovery_codes::ActiveModel {
+            id: Set(Ulid::new().to_string()),
+            user_id: Set(user_id.clone()),
+            code_hash: Set(utils::hash_password(&code)?),
+            used_at: Set(None),
+            created_at: Set(now),
+        });
+        recovery_codes.push(code);
+    }
+
+    // Two-factor must never be on without the recovery codes just shown.
+    let txn = db::pool().begin().await?;
+    let mut user: users::ActiveModel = user.into();
+    user.totp_enabled = Set(true);
+    user.totp_last_step = Set(Some(step));
+    user.updated_at = Set(now);
+    user.update(&txn).await?;
+    UserRecoveryCodes::delete_many()
+        .filter(user_recovery_codes::Column::UserId.eq(&user_id))
+        .exec(&txn)
+        .await?;
+    UserRecoveryCodes::insert_many(models).exec(&txn).await?;
+    txn.commit().await?;
+    json_ok(RecoveryCodesOutData { recovery_codes })
+}
+
+/// Turns two-factor off. Requires a current TOTP or recovery code.
+#[endpoint(tags("mfa"), security(("bearer" = [])))]
+pub async fn disable_totp(idata: JsonBody<TotpCodeInData>, depot: &mut Depot) -> EmptyResult {
+    let user = current_user(depot).await?;
+    if !user.totp_enabled {
+        return Err(AppError::public("Two-factor authentication is not enabled."));
+    }
+
+    let txn = db::pool().begin().await?;
+    if !verify_second_factor(&txn, &user, &idata.code).await? {
+        return Err(ErrorCode::IncorrectCode.into());
+    }
+    UserRecoveryCodes::delete_many()
+        .filter(user_recovery_codes::Column::UserId.eq(&user.id))
+        .exec(&txn)
+        .await?;
+    let mut user: users::ActiveModel = user.into();
+    user.totp_enabled = Set(false);
+    user.totp_secret = Set(None);
+    user.updated_at = Set(utils::now_primitive());
+    user.update(&txn).await?;
+    txn.commit().await?;
+    empty_ok()
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct LoginMfaInData {
+    pub mfa_token: String,
+    /// A TOTP code or one of the recovery codes.
+    pub code: String,
+}
+
+/// Second step of the login for accounts with two-factor enabled.
+#[endpoint(tags("auth"))]
+pub async fn post_login_mfa(
+    idata: JsonBody<LoginMfaInData>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> JsonResult<LoginOutData> {
+    let idata = idata.into_inner();
+    let expired = || {
+        AppError::from(StatusError::unauthorized().brief("The login has expired, please sign in again."))
+    };
+    let claims = jwt::decode_mfa_pending_token(&idata.mfa_token).ok_or_else(expired)?;
+    let Some(user) = Users::find_active_by_id(&claims.mfa_uid).one(db::pool()).await? else {
+        return Err(auth::login_failed());
+    };
+
+    let now = utils::now_primitive();
+    if user.locked_until.is_some_and(|until| until > now) {
+        return Err(auth::login_failed());
+    }
+    if !verify_second_factor(db::pool(), &user, &idata.code).await? {
+        auth::record_failed_login(&user, now, req, depot).await?;
+        return Err(auth::login_failed());
+    }
+    // Only after the code matched, so a typo doesn't cost the pending login,
+    // while a replayed token and code can't mint a second session.
+    let consumed = one_time_token::consume(&claims.jti, one_time_token::PURPOSE_MFA_PENDING).await?;
+    if consumed.as_deref() != Some(user.id.as_str()) {
+        return Err(expired());
+    }
+    json_ok(auth::complete_login(user, req, depot, res).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totp_codes_are_accepted_once() {
+        let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, b"12345678901234567890".to_vec(), None, "a@b.c".into())
+            .unwrap();
+        let now = 1_700_000_000;
+        let step = (now / 30) as i64;
+        let code = totp.generate(now);
+        assert_eq!(totp_step(&totp, &code, now, None), Some(step));
+        assert_eq!(totp_step(&totp, &format!(" {code} "), now, Some(step - 1)), Some(step));
+        assert_eq!(totp_step(&totp, &code, now, Some(step)), None);
+        // The previous step's code is still within the allowed skew, once.
+        let previous = totp.generate(now - 30);
+        assert_eq!(totp_step(&totp, &previous, now, Some(step - 2)), Some(step - 1));
+        assert_eq!(totp_step(&totp, &previous, now, Some(step)), None);
+        assert_eq!(totp_step(&totp, &totp.generate(now - 90), now, None), None);
+    }
+
+    fn user(totp_secret: Option<&str>, totp_last_step: Option<i64>) -> users::Model {
+        let now = utils::now_primitive();
+        users::Model {
+            id: "u1".into(),
+            email: "a@example.com".into(),
+            password: String::new(),
+            is_vip: false,
+            vip_start_time: None,
+            vip_end_time: None,
+            vip_level: 0,
+            created_at: now,
+            updated_at: now,
+            failed_login_count: 0,
+            locked_until: None,
+            totp_secret: totp_secret.map(str::to_owned),
+            totp_enabled: true,
+            email_verified_at: None,
+            is_admin: false,
+            deleted_at: None,
+            deletion_scheduled_at: None,
+            anonymized_at: None,
+            locale: None,
+            password_set: true,
+            totp_last_step,
+        }
+    }
+
+    #[test]
+    fn check_totp_uses_the_stored_secret_and_last_step() {
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        let now = 1_700_000_000;
+        let step = (now / 30) as i64;
+        let code = totp_for(&user(Some(secret), None), secret, None).unwrap().generate(now);
+        assert_eq!(check_totp(&user(Some(secret), None), &code, now).unwrap(), Some(step));
+        assert_eq!(check_totp(&user(Some(secret), Some(step)), &code, now).unwrap(), None);
+        assert_eq!(check_totp(&user(Some(secret), None), "000000", now).unwrap(), None);
+        assert_eq!(check_totp(&user(None, None), &code, now).unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_codes_match_once_hashed() {
+        let code = new_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(code.as_bytes()[5], b'-');
+        assert!(code.chars().all(|c| c == '-' || c.is_ascii_lowercase() || c.is_ascii_digit()));
+
+        let now = utils::now_primitive();
+        let candidate = |id: &str, code: &str| user_recovery_codes::Model {
+            id: id.into(),
+            user_id: "u1".into(),
+            code_hash: utils::hash_password(code).unwrap(),
+            used_at: None,
+            created_at: now,
+        };
+        let candidates = vec![candidate("r1", &new_recovery_code()), candidate("r2", &code)];
+        let typed = format!(" {} ", code.to_uppercase());
+        assert_eq!(find_recovery_code(candidates.clone(), &typed).map(|c| c.id), Some("r2".into()));
+        assert!(find_recovery_code(candidates, "aaaaa-aaaaa").is_none());
+    }
+}
@@ -2,11 +2,25 @@ use rust_embed::RustEmbed;
 use salvo::prelude::*;
 use salvo::serve_static::{static_embed, EmbeddedFileExt};
 
+mod account;
+mod admin;
+mod api_key;
 mod auth;
+mod bulk;
 mod demo;
+mod magic_link;
+mod metrics;
+mod mfa;
+mod oauth;
+pub(crate) mod privacy;
+mod session;
+mod stats;
 mod user;
+mod well_known;
 
-use crate::{config, hoops};
+use crate::hoops::{self, rate_limit};
+use crate::config;
+use crate::error::ErrorCode;
 
 #[derive(RustEmbed)]
 #[folder = "assets"]
@@ -16,30 +30,183 @@ pub fn root() -> Router {
     let favicon = Assets::get("favicon.ico")
         .expect("favicon not found")
         .into_handler();
-    let router = Router::new()
+    let rate_limit_config = &config::get().rate_limit;
+    let mut router = Router::new()
+        .hoop(hoops::metrics_hoop)
+        .hoop(hoops::trace_context_hoop)
+        .hoop(hoops::request_id_hoop)
         .hoop(Logger::new())
         .get(demo::hello)
         .push(Router::with_path("login").get(auth::login_page))
-        .push(Router::with_path("users").get(user::list_page))
+        .push(
+            Router::with_path("users")
+                .hoop(hoops::jwt::page_auth_hoop())
+                .hoop(hoops::admin_page_hoop)
+                .get(user::list_page)
+                .push(Router::with_path("{user_id}").get(user::detail_page)),
+        )
+        .push(Router::with_path("verify-email").get(account::verify_email_page))
+        .push(Router::with_path("reset-password").get(account::reset_password_page))
         .push(
             Router::with_path("api")
-                .push(Router::with_path("login").post(auth::post_login))
-                .push(Router::with_path("users").post(user::create_user))
+                .push(
+                    Router::with_path("login")
+                        .hoop(rate_limit::ip_limiter(&rate_limit_config.login_per_ip))
+                        .hoop(rate_limit::account_limiter(&rate_limit_config.login_per_account))
+                        .post(auth::post_login),
+                )
+                .push(
+                    Router::with_path("login/mfa")
+                        .hoop(rate_limit::ip_limiter(&rate_limit_config.login_per_ip))
+                        .post(mfa::post_login_mfa),
+                )
+                .push(
+                    Router::with_path("login/magic-link")
+                        .hoop(rate_limit::ip_limiter(&rate_limit_config.magic_link_per_ip))
+                        .hoop(rate_limit::account_limiter(&rate_limit_config.magic_link_per_account))
+                        .post(magic_link::request_magic_link),
+                )
+                .push(
+                    Router::with_path("login/magic-link/consume")
+                        .hoop(rate_limit::ip_limiter(&rate_limit_config.login_per_ip))
+                        .get(magic_link::consume_magic_link),
+                )
+                .push(
+                    Router::with_path("oauth/{provider}")
+                        .hoop(rate_limit::ip_limiter(&rate_limit_config.login_per_ip))
+                        .push(Router::with_path("authorize").get(oauth::authorize))
+                        .push(Router::with_path("callback").get(oauth::callback)),
+                )
+                .push(
+                    Router::with_path("account")
+                        .push(Router::with_path("verify-email").post(account::verify_email))
+                        .push(
+                            Router::with_path("password/reset").post(account::reset_password),
+                        )
+                        .push(
+                            Router::with_path("password/forgot")
+                                .hoop(rate_limit::ip_limiter(&rate_limit_config.account_email_per_ip))
+                                .hoop(rate_limit::account_limiter(&rate_limit_config.account_email_per_account))
+                                .post(account::forgot_password),
+                        )
+                        .push(
+                            authenticated(Router::with_path("verify-email/resend"))
+                                .hoop(rate_limit::ip_limiter(&rate_limit_config.account_email_per_ip))
+                                .post(account::resend_verification_email),
+                        ),
+                )
+                .push(Router::with_path("exports/download").get(privacy::download_export))
+                .push(
+                    authenticated(Router::with_path("me"))
+                        .push(
+                            Router::new()
+                                .hoop(hoops::forbid_impersonation_hoop)
+                                .delete(privacy::delete_me)
+                                .push(Router::with_path("locale").put(account::update_locale))
+                                .push(Router::with_path("deletion").delete(privacy::cancel_deletion))
+                                .push(
+                                    Router::with_path("export")
+                                        .post(privacy::request_export)
+                                        .push(Router::with_path("{export_id}").get(privacy::get_export)),
+                                ),
+                        )
+                        .push(
+                            Router::with_path("mfa/totp")
+                                .hoop(hoops::forbid_impersonation_hoop)
+                                .push(Router::with_path("enroll").post(mfa::enroll_totp))
+                                .push(Router::with_path("confirm").post(mfa::confirm_totp))
+                                .push(Router::with_path("disable").post(mfa::disable_totp)),
+                        )
+                        .push(
+                            Router::with_path("api-keys")
+                                .hoop(hoops::forbid_impersonation_hoop)
+                                .get(api_key::list_api_keys)
+                                .post(api_key::create_api_key)
+                                .push(Router::with_path("{key_id}").delete(api_key::revoke_api_key)),
+                        )
+                        .push(
+                            Router::with_path("sessions")
+                                .hoop(hoops::forbid_impersonation_hoop)
+                                .get(session::list_my_sessions)
+                                .push(
+                                    Router::with_path("{session_id}")
+                                        .delete(session::revoke_my_session),
+                                ),
+                        ),
+                )
                 .push(
                     Router::with_path("users")
-                        .hoop(hoops::auth_hoop(&config::get().jwt))
+                        .hoop(rate_limit::ip_limiter(&rate_limit_config.register_per_ip))
+                        .hoop(rate_limit::account_limiter(&rate_limit_config.register_per_account))
+                        .post(user::create_user),
+                )
+                .push(
+                    authenticated(Router::with_path("users"))
                         .get(user::list_users)
                         .push(
                             Router::with_path("{user_id}")
+                                .hoop(hoops::forbid_impersonation_hoop)
                                 .put(user::update_user)
                                 .delete(user::delete_user),
                         ),
+                )
+                .push(
+                    authenticated(Router::with_path("admin"))
+                        .push(Router::with_path("audit").get(admin::list_audit_events))
+                        .push(Router::with_path("stats").get(stats::get_stats))
+                        .push(Router::with_path("users/import").post(bulk::import_users))
+                        .push(Router::with_path("users/export").get(bulk::export_users))
+                        .push(Router::with_path("users/{user_id}/restore").post(admin::restore_user))
+                        .push(
+                            Router::with_path("users/{user_id}/vip")
+                                .hoop(hoops::forbid_impersonation_hoop)
+                                .put(admin::update_vip),
+                        )
+                        .push(
+                            Router::with_path("users/{user_id}/impersonate")
+                                .post(admin::impersonate_user),
+                        )
+                        .push(
+                            Router::with_path("users/{user_id}/sessions")
+                                .get(session::list_user_sessions)
+                                .delete(session::revoke_user_sessions)
+                                .push(
+                                    Router::with_path("{session_id}")
+                                        .delete(session::revoke_user_session),
+                                ),
+                        ),
                 ),
         )
+        .push(Router::with_path(".well-known/jwks.json").get(well_known::jwks))
         .push(Router::with_path("favicon.ico").get(favicon))
         .push(Router::with_path("assets/{**rest}").get(static_embed::<Assets>()));
-    let doc = OpenApi::new("salvo web api", "0.0.1").merge_router(&router);
+    let metrics_config = &config::get().metrics;
+    if metrics_config.enabled && metrics_config.listen_addr.is_none() {
+        router = router.push(metrics_router());
+    }
+    let mut doc = OpenApi::new("salvo web api", "0.0.1").merge_router(&router);
+    doc.info.description = Some(format!(
+        "Errors respond with `{{ code, msg, data }}`. `code` is the HTTP status, or one of these business codes:\n\n{}",
+        ErrorCode::catalog()
+    ));
+    let doc = hoops::scope::register(doc);
     router
         .unshift(doc.into_router("/api-doc/openapi.json"))
         .unshift(Scalar::new("/api-doc/openapi.json").into_router("scalar"))
 }
+
+/// Requires a valid token (and the CSRF token when it came from the cookie),
+/// audits impersonated requests and checks the scopes the endpoint declares,
+/// in that order.
+fn authenticated(router: Router) -> Router {
+    router
+        .hoop(hoops::auth_hoop())
+        .hoop(hoops::csrf_hoop)
+        .hoop(hoops::impersonation_audit_hoop)
+        .hoop(hoops::scope_hoop)
+}
+
+/// The scrape endpoint, either mounted on [`root`] or served on its own listener.
+pub fn metrics_router() -> Router {
+    Router::with_path("metrics").get(metrics::scrape)
+}
@@ -0,0 +1,158 @@
+use cookie::{Cookie, SameSite};
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use ulid::Ulid;
+
+use super::{auth, user};
+use crate::config::OidcProviderConfig;
+use crate::entities::{prelude::*, user_identities, users};
+use crate::hoops::jwt;
+use crate::oidc::{self, Identity};
+use crate::{config, db, utils, AppError, AppResult};
+
+const STATE_COOKIE: &str = "oidc_state";
+const STATE_COOKIE_PATH: &str = "/api/oauth";
+
+fn find_provider(name: &str) -> AppResult<&'static OidcProviderConfig> {
+    config::get()
+        .oidc
+        .provider(name)
+        .ok_or_else(|| StatusError::not_found().brief("Unknown login provider.").into())
+}
+
+/// Redirects to the provider's consent screen.
+#[endpoint(tags("auth"))]
+pub async fn authorize(provider: PathParam<String>, res: &mut Response) -> AppResult<()> {
+    let provider = find_provider(&provider)?;
+    let redirect_uri = config::get().oidc.redirect_uri(&provider.name);
+    let request = oidc::authorization_request(provider, &redirect_uri)?;
+    let state_token = jwt::get_oidc_state_token(
+        &provider.name,
+        request.state,
+        request.nonce,
+        request.code_verifier,
+    )?;
+    // Lax, so the cookie comes back on the provider's top-level redirect.
+    let cookie = Cookie::build((STATE_COOKIE, state_token))
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        .secure(config::get().cookie.is_secure())
+        .same_site(SameSite::Lax)
+        .build();
+    res.add_cookie(cookie);
+    res.render(Redirect::found(request.url));
+    Ok(())
+}
+
+/// Finishes the authorization code flow and logs the linked account in.
+#[endpoint(tags("auth"))]
+pub async fn callback(
+    provider: PathParam<String>,
+    code: QueryParam<String, false>,
+    state: QueryParam<String, false>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> AppResult<()> {
+    let provider = find_provider(&provider)?;
+    let failed = || AppError::public("Social login failed, please try again.");
+    let saved = req
+        .cookie(STATE_COOKIE)
+        .and_then(|cookie| jwt::decode_oidc_state_token(cookie.value()))
+        .ok_or_else(failed)?;
+    let expired = Cookie::build((STATE_COOKIE, ""))
+        .path(STATE_COOKIE_PATH)
+        .max_age(cookie::time::Duration::ZERO)
+        .build();
+    res.add_cookie(expired);
+    if saved.oidc_provider != provider.name || state.as_deref() != Some(saved.state.as_str()) {
+        return Err(failed());
+    }
+    // Absent when the user denied consent; the provider then sends `error` instead.
+    let code = code.into_inner().ok_or_else(failed)?;
+
+    let redirect_uri = config::get().oidc.redirect_uri(&provider.name);
+    let identity = oidc::exchange_code(
+        provider,
+        &redirect_uri,
+        &code,
+        &saved.code_verifier,
+        &saved.nonce,
+    )
+    .await
+    .map_err(|e| {
+        tracing::warn!(provider = %provider.name, error = %e, "oidc code exchange failed");
+        failed()
+    })?;
+    let user = link_identity(&provider.name, identity).await?;
+
+    if user.locked_until.is_some_and(|until| until > utils::now_primitive()) {
+        return Err(auth::login_failed());
+    }
+    // The provider replaces the password, not our own second factor.
+    if user.totp_enabled {
+        return Err(StatusError::forbidden()
+            .brief("Two-factor authentication is enabled, please sign in with your password.")
+            .into());
+    }
+    auth::complete_login(user, req, depot, res).await?;
+    res.render(Redirect::other(&config::get().oidc.redirect_after_login));
+    Ok(())
+}
+
+/// Resolves the local account for a provider identity.
+///
+/// Known identities log into their account. New ones are linked to the account
+/// with the same verified email, or get a fresh account when there is none.
+async fn link_identity(provider: &str, identity: Identity) -> AppResult<users::Model> {
+    let conn = db::pool();
+    let now = utils::now_primitive();
+    if let Some(linked) = UserIdentities::find()
+        .filter(user_identities::Column::Provider.eq(provider))
+        .filter(user_identities::Column::Subject.eq(&identity.subject))
+        .one(conn)
+        .await?
+    {
+        let user_id = linked.user_id.clone();
+        let mut linked: user_identities::ActiveModel = linked.into();
+        linked.last_login_at = Set(now);
+        linked.update(conn).await?;
+        return Users::find_active_by_id(user_id)
+            .one(conn)
+            .await?
+            .ok_or_else(|| StatusError::unauthorized().into());
+    }
+
+    let Some(email) = identity.email.filter(|_| identity.email_verified) else {
+        return Err(AppError::public(
+            "The provider did not share a verified email address.",
+        ));
+    };
+    let user = match Users::find_active()
+        .filter(users::Column::Email.eq(&email))
+        .one(conn)
+        .await?
+    {
+        // Linking to an unverified account would hand it to whoever registered
+        // the address first.
+        Some(user) if user.email_verified_at.is_none() => {
+            return Err(AppError::public(
+                "Please verify your email address before linking a social login.",
+            ));
+        }
+        Some(user) => user,
+        None => user::insert_user(email.clone(), None, Some(now)).await?,
+    };
+    let link = user_identities::ActiveModel {
+        id: Set(Ulid::new().to_string()),
+        user_id: Set(user.id.clone()),
+        provider: Set(provider.to_owned()),
+        subject: Set(identity.subject),
+        email: Set(Some(email)),
+        created_at: Set(now),
+        last_login_at: Set(now),
+    };
+    UserIdentities::insert(link).exec(conn).await?;
+    Ok(user)
+}
@@ -0,0 +1,451 @@
+use rinja::Template;
+use salvo::fs::NamedFile;
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use super::api_key::ApiKeyOutData;
+use super::session::SessionOutData;
+use crate::audit::{self, Event};
+use crate::entities::{prelude::*, *};
+use crate::hoops::jwt;
+use crate::mailer::{self, Mail};
+use crate::models::SafeUser;
+use crate::error::ErrorCode;
+use crate::i18n::{Locale, Tr};
+use crate::{config, db, json_ok, utils, AppError, AppResult, JsonResult};
+
+pub const EXPORT_PENDING: &str = "pending";
+pub const EXPORT_READY: &str = "ready";
+pub const EXPORT_FAILED: &str = "failed";
+
+#[derive(Template)]
+#[template(path = "emails/data_export.html")]
+struct DataExportMail<'a> {
+    email: &'a str,
+    link: &'a str,
+    expires_in_hours: i64,
+    tr: Tr,
+}
+
+#[derive(Template)]
+#[template(path = "emails/account_deletion.html")]
+struct AccountDeletionMail<'a> {
+    email: &'a str,
+    scheduled_at: &'a str,
+    tr: Tr,
+}
+
+/// The signed-in account, for requests made by the person themselves rather
+/// than an API key.
+async fn interactive_user(depot: &Depot) -> AppResult<users::Model> {
+    let claims = jwt::current_claims(depot)?;
+    if claims.is_api_key() {
+        return Err(StatusError::forbidden()
+            .brief("This needs a signed-in session, not an API key.")
+            .into());
+    }
+    Users::find_active_by_id(claims.user_id())
+        .one(db::pool())
+        .await?
+        .ok_or_else(|| StatusError::unauthorized().into())
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct DataExportOutData {
+    pub id: String,
+    /// `pending`, `ready` or `failed`.
+    pub status: String,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub created_at: time::PrimitiveDateTime,
+    #[serde(serialize_with = "crate::models::serialize_optional_primitive_datetime")]
+    pub expires_at: Option<time::PrimitiveDateTime>,
+    /// Present once the export is ready; also sent by email.
+    pub download_url: Option<String>,
+}
+
+impl DataExportOutData {
+    fn new(model: data_exports::Model) -> AppResult<Self> {
+        let download_url = match model.expires_at {
+            Some(expires_at) if model.status == EXPORT_READY => Some(download_url(
+                &model.id,
+                &model.user_id,
+                expires_at.assume_utc().unix_timestamp(),
+            )?),
+            _ => None,
+        };
+        Ok(Self {
+            id: model.id,
+            status: model.status,
+            created_at: model.created_at,
+            expires_at: model.expires_at,
+            download_url,
+        })
+    }
+}
+
+fn download_url(export_id: &str, user_id: &str, exp: i64) -> AppResult<String> {
+    let token = jwt::get_data_export_token(export_id, user_id, exp)?;
+    Ok(format!(
+        "{}/api/exports/download?token={}",
+        config::get().mail.link_base_url,
+        token
+    ))
+}
+
+/// Starts building an archive of everything stored about the caller. The
+/// download link is mailed when it's ready; poll `GET /api/me/export/{export_id}`
+/// meanwhile.
+#[endpoint(tags("privacy"), security(("bearer" = [])))]
+pub async fn request_export(req: &mut Request, depot: &mut Depot) -> JsonResult<DataExportOutData> {
+    let user = interactive_user(depot).await?;
+    let conn = db::pool();
+    // An older pending export was lost, e.g. to a restart, and the purge job
+    // will mark it failed.
+    let stale_before =
+        utils::now_primitive() - time::Duration::seconds(config::get().privacy.export_timeout);
+    if let Some(pending) = DataExports::find()
+        .filter(data_exports::Column::UserId.eq(&user.id))
+        .filter(data_exports::Column::Status.eq(EXPORT_PENDING))
+        .filter(data_exports::Column::CreatedAt.gte(stale_before))
+        .one(conn)
+        .await?
+    {
+        return json_ok(DataExportOutData::new(pending)?);
+    }
+    let model = data_exports::ActiveModel {
+        id: Set(Ulid::new().to_string()),
+        user_id: Set(user.id.clone()),
+        status: Set(EXPORT_PENDING.to_owned()),
+        created_at: Set(utils::now_primitive()),
+        completed_at: Set(None),
+        expires_at: Set(None),
+    };
+    let export = DataExports::insert(model).exec_with_returning(conn).await?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_DATA_EXPORT,
+            target_type: Some("data_export"),
+            target_id: Some(&export.id),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let export_id = export.id.clone();
+    tokio::spawn(async move {
+        if let Err(e) = build_export(&export_id, user).await {
+            tracing::error!(error = ?e, export_id, "failed to build data export");
+            let _ = DataExports::update_many()
+                .col_expr(data_exports::Column::Status, Expr::value(EXPORT_FAILED))
+                .filter(data_exports::Column::Id.eq(&export_id))
+                .exec(db::pool())
+                .await;
+        }
+    });
+    json_ok(DataExportOutData::new(export)?)
+}
+
+#[endpoint(tags("privacy"), security(("bearer" = [])))]
+pub async fn get_export(export_id: PathParam<String>, depot: &mut Depot) -> JsonResult<DataExportOutData> {
+    let user = interactive_user(depot).await?;
+    let Some(export) = DataExports::find_by_id(export_id.into_inner())
+        .filter(data_exports::Column::UserId.eq(&user.id))
+        .one(db::pool())
+        .await?
+    else {
+        return Err(StatusError::not_found().brief("Export not found.").into());
+    };
+    json_ok(DataExportOutData::new(export)?)
+}
+
+/// Serves a finished export to whoever holds its emailed link.
+#[endpoint(tags("privacy"))]
+pub async fn download_export(
+    token: QueryParam<String, true>,
+    req: &mut Request,
+    res: &mut Response,
+) -> AppResult<()> {
+    let invalid = || AppError::business(ErrorCode::InvalidLink, "The download link is invalid or has expired.");
+    let claims = jwt::decode_data_export_token(&token).ok_or_else(invalid)?;
+    let export = DataExports::find_by_id(claims.export_id)
+        .filter(data_exports::Column::UserId.eq(claims.export_uid))
+        .filter(data_exports::Column::Status.eq(EXPORT_READY))
+        .filter(data_exports::Column::ExpiresAt.gt(utils::now_primitive()))
+        .one(db::pool())
+        .await?
+        .ok_or_else(invalid)?;
+    let path = config::get().privacy.export_path(&export.id);
+    NamedFile::builder(path)
+        .attached_name(format!("ttbox-export-{}.json", export.id))
+        .send(req.headers(), res)
+        .await;
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+struct ExportArchive {
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    exported_at: time::PrimitiveDateTime,
+    profile: SafeUser,
+    settings: ExportSettings,
+    vip_history: Vec<VipChange>,
+    /// Distinct devices the account signed in from.
+    devices: Vec<String>,
+    sessions: Vec<SessionOutData>,
+    linked_identities: Vec<LinkedIdentity>,
+    api_keys: Vec<ApiKeyOutData>,
+}
+
+#[derive(Serialize, Debug)]
+struct ExportSettings {
+    #[serde(serialize_with = "crate::models::serialize_optional_primitive_datetime")]
+    email_verified_at: Option<time::PrimitiveDateTime>,
+    totp_enabled: bool,
+    #[serde(serialize_with = "crate::models::serialize_optional_primitive_datetime")]
+    deletion_scheduled_at: Option<time::PrimitiveDateTime>,
+}
+
+#[derive(Serialize, Debug)]
+struct VipChange {
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    changed_at: time::PrimitiveDateTime,
+    diff: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct LinkedIdentity {
+    provider: String,
+    email: Option<String>,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    created_at: time::PrimitiveDateTime,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    last_login_at: time::PrimitiveDateTime,
+}
+
+/// Writes the archive to `privacy.export_directory`, marks the export ready and
+/// mails the link.
+async fn build_export(export_id: &str, user: users::Model) -> AppResult<()> {
+    let conn = db::pool();
+    let privacy = &config::get().privacy;
+    let vip_history = AuditEvents::find()
+        .filter(audit_events::Column::Action.eq(audit::ACTION_VIP_UPDATE))
+        .filter(audit_events::Column::TargetType.eq("user"))
+        .filter(audit_events::Column::TargetId.eq(&user.id))
+        .order_by_asc(audit_events::Column::CreatedAt)
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|event| VipChange {
+            changed_at: event.created_at,
+            diff: event.diff,
+        })
+        .collect();
+    let sessions: Vec<SessionOutData> = Sessions::find()
+        .filter(sessions::Column::UserId.eq(&user.id))
+        .order_by_asc(sessions::Column::CreatedAt)
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|session| SessionOutData::new(session, None))
+        .collect();
+    let mut devices: Vec<String> = sessions.iter().map(|s| s.device.clone()).collect();
+    devices.sort();
+    devices.dedup();
+    let linked_identities = UserIdentities::find()
+        .filter(user_identities::Column::UserId.eq(&user.id))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|identity| LinkedIdentity {
+            provider: identity.provider,
+            email: identity.email,
+            created_at: identity.created_at,
+            last_login_at: identity.last_login_at,
+        })
+        .collect();
+    let api_keys = ApiKeys::find()
+        .filter(api_keys::Column::UserId.eq(&user.id))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    let now = utils::now_primitive();
+    let archive = ExportArchive {
+        exported_at: now,
+        settings: ExportSettings {
+            email_verified_at: user.email_verified_at,
+            totp_enabled: user.totp_enabled,
+            deletion_scheduled_at: user.deletion_scheduled_at,
+        },
+        profile: SafeUser::from(user.clone()),
+        vip_history,
+        devices,
+        sessions,
+        linked_identities,
+        api_keys,
+    };
+    let json = serde_json::to_vec_pretty(&archive).map_err(|e| AppError::internal(e.to_string()))?;
+    tokio::fs::create_dir_all(&privacy.export_directory)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    tokio::fs::write(privacy.export_path(export_id), json)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+
+    let expires_at = now + time::Duration::seconds(privacy.export_expiry);
+    let export = data_exports::ActiveModel {
+        id: Set(export_id.to_owned()),
+        status: Set(EXPORT_READY.to_owned()),
+        completed_at: Set(Some(now)),
+        expires_at: Set(Some(expires_at)),
+        ..Default::default()
+    };
+    export.update(conn).await?;
+
+    let link = download_url(export_id, &user.id, expires_at.assume_utc().unix_timestamp())?;
+    let tr = Tr(Locale::saved_or_default(user.locale.as_deref()));
+    let html = DataExportMail {
+        email: &user.email,
+        link: &link,
+        expires_in_hours: privacy.export_expiry / 3600,
+        tr,
+    }
+    .render()
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    mailer::send_later(Mail {
+        to: user.email,
+        subject: tr.t("Your data export is ready").into_owned(),
+        html,
+    });
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct DeleteAccountInData {
+    /// The current password, to confirm it's really the account owner. Only
+    /// accounts without one, created by a social login, may leave it out.
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct AccountDeletionOutData {
+    /// When the account will be anonymised; `null` once a deletion is cancelled.
+    #[serde(serialize_with = "crate::models::serialize_optional_primitive_datetime")]
+    pub deletion_scheduled_at: Option<time::PrimitiveDateTime>,
+}
+
+/// Schedules the caller's account for anonymisation after the cooling-off period
+/// in `privacy.deletion_cooling_off_days`. Until then it can be cancelled.
+///
+/// Needs the password. Accounts without one instead need a session signed in
+/// within `privacy.reauth_window` seconds.
+#[endpoint(tags("privacy"), security(("bearer" = [])))]
+pub async fn delete_me(
+    idata: JsonBody<DeleteAccountInData>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<AccountDeletionOutData> {
+    let user = interactive_user(depot).await?;
+    if user.password_set {
+        let password = idata.password.as_deref().unwrap_or_default();
+        if utils::verify_password(password, &user.password).is_err() {
+            return Err(StatusError::forbidden().brief("Password is incorrect.").into());
+        }
+    } else if !signed_in_recently(depot).await? {
+        return Err(StatusError::forbidden()
+            .brief("Please sign in again to confirm.")
+            .into());
+    }
+    if user.deletion_scheduled_at.is_some() {
+        return json_ok(AccountDeletionOutData {
+            deletion_scheduled_at: user.deletion_scheduled_at,
+        });
+    }
+    let now = utils::now_primitive();
+    let scheduled_at =
+        now + time::Duration::days(config::get().privacy.deletion_cooling_off_days);
+    let email = user.email.clone();
+    let mut user: users::ActiveModel = user.into();
+    user.deletion_scheduled_at = Set(Some(scheduled_at));
+    user.updated_at = Set(now);
+    let user = user.update(db::pool()).await?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_ACCOUNT_DELETION_REQUEST,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            diff: Some(serde_json::json!({ "scheduled_at": scheduled_at.to_string() })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let tr = Tr(Locale::saved_or_default(user.locale.as_deref()));
+    let html = AccountDeletionMail {
+        email: &email,
+        scheduled_at: &scheduled_at.date().to_string(),
+        tr,
+    }
+    .render()
+    .map_err(|e| AppError::internal(e.to_string()))?;
+    mailer::send_later(Mail {
+        to: email,
+        subject: tr.t("Your account will be deleted").into_owned(),
+        html,
+    });
+    json_ok(AccountDeletionOutData {
+        deletion_scheduled_at: user.deletion_scheduled_at,
+    })
+}
+
+/// Whether the caller's session was created within `privacy.reauth_window`
+/// seconds, i.e. they just proved who they are by signing in.
+async fn signed_in_recently(depot: &Depot) -> AppResult<bool> {
+    let Some(sid) = jwt::current_claims(depot)?.sid.as_deref() else {
+        return Ok(false);
+    };
+    let since = utils::now_primitive() - time::Duration::seconds(config::get().privacy.reauth_window);
+    let session = Sessions::find_by_id(sid)
+        .filter(sessions::Column::RevokedAt.is_null())
+        .filter(sessions::Column::CreatedAt.gte(since))
+        .one(db::pool())
+        .await?;
+    Ok(session.is_some())
+}
+
+/// Keeps the account after all.
+#[endpoint(tags("privacy"), security(("bearer" = [])))]
+pub async fn cancel_deletion(req: &mut Request, depot: &mut Depot) -> JsonResult<AccountDeletionOutData> {
+    let user = interactive_user(depot).await?;
+    if user.deletion_scheduled_at.is_none() {
+        return Err(StatusError::not_found().brief("No deletion is scheduled.").into());
+    }
+    let mut user: users::ActiveModel = user.into();
+    user.deletion_scheduled_at = Set(None);
+    user.updated_at = Set(utils::now_primitive());
+    let user = user.update(db::pool()).await?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_ACCOUNT_DELETION_CANCEL,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            ..Default::default()
+        },
+    )
+    .await;
+    json_ok(AccountDeletionOutData {
+        deletion_scheduled_at: None,
+    })
+}
@@ -0,0 +1,143 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use serde::Serialize;
+
+use crate::audit::{self, Event};
+use crate::entities::{prelude::Sessions, sessions};
+use crate::hoops::jwt;
+use crate::utils::session;
+use crate::{db, empty_ok, json_ok, utils, AppResult, EmptyResult, JsonResult};
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SessionOutData {
+    pub id: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub device: String,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub created_at: time::PrimitiveDateTime,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub last_used_at: time::PrimitiveDateTime,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub expires_at: time::PrimitiveDateTime,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+impl SessionOutData {
+    pub fn new(model: sessions::Model, current_sid: Option<&str>) -> Self {
+        Self {
+            current: current_sid == Some(model.id.as_str()),
+            id: model.id,
+            ip: model.ip,
+            user_agent: model.user_agent,
+            device: model.device,
+            created_at: model.created_at,
+            last_used_at: model.last_used_at,
+            expires_at: model.expires_at,
+        }
+    }
+}
+
+async fn active_sessions(user_id: &str) -> AppResult<Vec<sessions::Model>> {
+    Ok(Sessions::find()
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::RevokedAt.is_null())
+        .filter(sessions::Column::ExpiresAt.gt(utils::now_primitive()))
+        .order_by_desc(sessions::Column::LastUsedAt)
+        .all(db::pool())
+        .await?)
+}
+
+fn session_not_found() -> crate::AppError {
+    StatusError::not_found().brief("Session not found.").into()
+}
+
+/// Devices currently signed in to the caller's account.
+#[endpoint(tags("sessions"), security(("bearer" = [])))]
+pub async fn list_my_sessions(depot: &mut Depot) -> JsonResult<Vec<SessionOutData>> {
+    let claims = jwt::current_claims(depot)?;
+    let sessions = active_sessions(claims.user_id()).await?;
+    let sid = claims.sid.as_deref();
+    json_ok(sessions.into_iter().map(|s| SessionOutData::new(s, sid)).collect())
+}
+
+/// Signs one of the caller's devices out; its tokens stop working immediately.
+#[endpoint(tags("sessions"), security(("bearer" = [])))]
+pub async fn revoke_my_session(session_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let user_id = jwt::current_claims(depot)?.user_id();
+    if session::revoke(user_id, Some(&session_id)).await? == 0 {
+        return Err(session_not_found());
+    }
+    empty_ok()
+}
+
+#[endpoint(
+    tags("admin"),
+    parameters(("user_id", description = "owner of the sessions")),
+    security(("bearer" = ["sessions:manage"]))
+)]
+pub async fn list_user_sessions(user_id: PathParam<String>) -> JsonResult<Vec<SessionOutData>> {
+    let sessions = active_sessions(&user_id).await?;
+    json_ok(sessions.into_iter().map(|s| SessionOutData::new(s, None)).collect())
+}
+
+/// Signs the user out of every device.
+#[endpoint(
+    tags("admin"),
+    parameters(("user_id", description = "owner of the sessions")),
+    security(("bearer" = ["sessions:manage"]))
+)]
+pub async fn revoke_user_sessions(
+    user_id: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> EmptyResult {
+    let revoked = session::revoke(&user_id, None).await?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_SESSION_REVOKE,
+            target_type: Some("user"),
+            target_id: Some(&user_id),
+            diff: Some(serde_json::json!({ "revoked": revoked })),
+            ..Default::default()
+        },
+    )
+    .await;
+    empty_ok()
+}
+
+#[endpoint(
+    tags("admin"),
+    parameters(
+        ("user_id", description = "owner of the session"),
+        ("session_id", description = "session to revoke"),
+    ),
+    security(("bearer" = ["sessions:manage"]))
+)]
+pub async fn revoke_user_session(
+    user_id: PathParam<String>,
+    session_id: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> EmptyResult {
+    if session::revoke(&user_id, Some(&session_id)).await? == 0 {
+        return Err(session_not_found());
+    }
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_SESSION_REVOKE,
+            target_type: Some("session"),
+            target_id: Some(&session_id),
+            diff: Some(serde_json::json!({ "user_id": *user_id })),
+            ..Default::default()
+        },
+    )
+    .await;
+    empty_ok()
+}
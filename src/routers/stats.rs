@@ -0,0 +1,175 @@
+//! Aggregate numbers for the admin dashboard. Every figure comes from a grouped
+//! query and the whole response is cached for `stats.cache_ttl` seconds, so the
+//! dashboard can poll without scanning `users` each time.
+//!
+//! Revenue per plan is not reported yet: there is no orders table to sum.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use salvo::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::entities::{prelude::Users, users};
+use crate::{config, db, json_ok, utils, AppResult, JsonResult};
+
+/// Days of daily sign-ups reported.
+const SIGNUP_DAYS: i64 = 30;
+/// Weeks of weekly sign-ups reported.
+const SIGNUP_WEEKS: i64 = 12;
+
+#[derive(Debug, Deserialize, Validate, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct StatsQuery {
+    /// Window for `vip_expiring` and `vip_churned`; defaults to `stats.window_days`.
+    #[validate(range(min = 1, max = 365, message = "days must be between 1 and 365"))]
+    #[salvo(parameter(minimum = 1, maximum = 365))]
+    pub days: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct DateCount {
+    /// `YYYY-MM-DD`; for weekly figures, the Monday starting the week.
+    pub date: String,
+    pub count: i64,
+}
+
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct LevelCount {
+    pub vip_level: i32,
+    pub count: i64,
+}
+
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct StatsOutData {
+    /// Users that aren't deleted.
+    pub total_users: u64,
+    /// Sign-ups per day over the last 30 days; days without any are omitted.
+    pub signups_daily: Vec<DateCount>,
+    /// Sign-ups per week over the last 12 weeks.
+    pub signups_weekly: Vec<DateCount>,
+    /// Current VIPs per `vip_level`.
+    pub vip_levels: Vec<LevelCount>,
+    /// VIP memberships ending within the next `window_days`, per day.
+    pub vip_expiring: Vec<DateCount>,
+    /// VIPs whose membership ended within the last `window_days` and was not renewed.
+    pub vip_churned: u64,
+    pub window_days: u32,
+    #[serde(serialize_with = "crate::models::serialize_primitive_datetime")]
+    pub generated_at: time::PrimitiveDateTime,
+}
+
+/// Computed statistics by window, with when they were computed.
+static CACHE: LazyLock<Mutex<HashMap<u32, (Instant, StatsOutData)>>> =
+    LazyLock::new(Default::default);
+
+/// User and VIP figures for the admin dashboard.
+#[endpoint(tags("admin"), security(("bearer" = ["stats:read"]), ("api_key" = ["stats:read"])))]
+pub async fn get_stats(query: StatsQuery) -> JsonResult<StatsOutData> {
+    query.validate()?;
+    let stats_config = &config::get().stats;
+    let days = query.days.unwrap_or(stats_config.window_days);
+    let ttl = Duration::from_secs(stats_config.cache_ttl);
+
+    if let Some((at, stats)) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).get(&days)
+        && at.elapsed() < ttl
+    {
+        return json_ok(stats.clone());
+    }
+    let stats = compute(days).await?;
+    CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(days, (Instant::now(), stats.clone()));
+    json_ok(stats)
+}
+
+async fn compute(days: u32) -> AppResult<StatsOutData> {
+    let conn = db::pool();
+    let now = utils::now_primitive();
+    let window = time::Duration::days(days.into());
+
+    let total_users = Users::find_active().count(conn).await?;
+    let signups_daily = count_by_date(
+        conn,
+        Users::find_active().filter(users::Column::CreatedAt.gte(now - time::Duration::days(SIGNUP_DAYS))),
+        "DATE(created_at)",
+    )
+    .await?;
+    let signups_weekly = count_by_date(
+        conn,
+        Users::find_active().filter(users::Column::CreatedAt.gte(now - time::Duration::weeks(SIGNUP_WEEKS))),
+        "DATE(DATE_SUB(created_at, INTERVAL WEEKDAY(created_at) DAY))",
+    )
+    .await?;
+
+    let vip_levels: Vec<(i32, i64)> = Users::find_active()
+        .select_only()
+        .column(users::Column::VipLevel)
+        .column_as(Expr::col(users::Column::Id).count(), "count")
+        .filter(users::Column::IsVip.eq(true))
+        .filter(
+            Condition::any()
+                .add(users::Column::VipEndTime.is_null())
+                .add(users::Column::VipEndTime.gt(now)),
+        )
+        .group_by(users::Column::VipLevel)
+        .order_by_asc(users::Column::VipLevel)
+        .into_tuple()
+        .all(conn)
+        .await?;
+    let vip_expiring = count_by_date(
+        conn,
+        Users::find_active()
+            .filter(users::Column::IsVip.eq(true))
+            .filter(users::Column::VipEndTime.gt(now))
+            .filter(users::Column::VipEndTime.lte(now + window)),
+        "DATE(vip_end_time)",
+    )
+    .await?;
+    let vip_churned = Users::find_active()
+        .filter(users::Column::IsVip.eq(true))
+        .filter(users::Column::VipEndTime.gt(now - window))
+        .filter(users::Column::VipEndTime.lte(now))
+        .count(conn)
+        .await?;
+
+    Ok(StatsOutData {
+        total_users,
+        signups_daily,
+        signups_weekly,
+        vip_levels: vip_levels
+            .into_iter()
+            .map(|(vip_level, count)| LevelCount { vip_level, count })
+            .collect(),
+        vip_expiring,
+        vip_churned,
+        window_days: days,
+        generated_at: now,
+    })
+}
+
+/// Counts the rows of `select` grouped by the SQL date expression `date`.
+async fn count_by_date(
+    conn: &impl ConnectionTrait,
+    select: Select<Users>,
+    date: &str,
+) -> AppResult<Vec<DateCount>> {
+    let rows: Vec<(time::Date, i64)> = select
+        .select_only()
+        .column_as(Expr::cust(date), "date")
+        .column_as(Expr::col(users::Column::Id).count(), "count")
+        .group_by(Expr::cust(date))
+        .order_by_asc(Expr::cust(date))
+        .into_tuple()
+        .all(conn)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(date, count)| DateCount { date: date.to_string(), count })
+        .collect())
+}
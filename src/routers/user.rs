@@ -1,15 +1,21 @@
 use rinja::Template;
 use salvo::oapi::extract::*;
 use salvo::prelude::*;
-use sea_orm::{ActiveModelTrait, EntityTrait, Set, QueryFilter, QuerySelect, ColumnTrait, PaginatorTrait};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sea_orm::{ActiveModelTrait, EntityTrait, Set, QueryFilter, QueryOrder, QuerySelect, ColumnTrait, Condition, PaginatorTrait, Select};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 use validator::Validate;
-use crate::hoops::jwt;
+use crate::audit::{self, Event};
+use crate::utils::{api_key, session};
+use super::account;
 
-use crate::entities::{prelude::Users, users};
+use crate::entities::{audit_events, prelude::{AuditEvents, Users}, users};
 use crate::models::SafeUser;
-use crate::{db, empty_ok, json_ok, utils, AppResult, EmptyResult, JsonResult};
+use crate::error::ErrorCode;
+use crate::i18n::{Locale, Message, Tr};
+use crate::{db, empty_ok, json_ok, utils, AppError, AppResult, EmptyResult, JsonResult, Paginated};
 
 #[derive(Template)]
 #[template(path = "user_list_page.html")]
@@ -19,15 +25,10 @@ pub struct UserListPageTemplate {}
 #[template(path = "user_list_frag.html")]
 pub struct UserListFragTemplate {}
 
+/// The admin user list. Reached through `admin_page_hoop`.
 #[handler]
 pub async fn list_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
     let is_fragment = req.headers().get("X-Fragment-Header");
-    if let Some(cookie) = res.cookies().get("jwt_token") {
-        let token = cookie.value().to_string();
-        if !jwt::decode_token(&token) {
-            res.render(Redirect::other("/login"));
-        }
-    }
     match is_fragment {
         Some(_) => {
             let hello_tmpl = UserListFragTemplate {};
@@ -41,6 +42,76 @@ pub async fn list_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
     Ok(())
 }
 
+/// Audit entries shown on the user detail page.
+const DETAIL_AUDIT_EVENTS: u64 = 50;
+
+pub struct AuditRow {
+    pub created_at: String,
+    pub action: String,
+    pub actor_id: String,
+    pub diff: String,
+}
+
+#[derive(Template)]
+#[template(path = "user_detail_page.html")]
+pub struct UserDetailPageTemplate {
+    pub user: SafeUser,
+    /// `user` for the page script, which edits it through the VIP dialog.
+    pub user_json: String,
+    pub vip_start_time: String,
+    pub vip_end_time: String,
+    pub created_at: String,
+    pub events: Vec<AuditRow>,
+    pub tr: Tr,
+}
+
+fn page_time(dt: Option<time::PrimitiveDateTime>) -> String {
+    dt.and_then(|dt| {
+        dt.assume_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .ok()
+    })
+    .unwrap_or_default()
+}
+
+/// One user with its VIP details and latest audit history. Reached through
+/// `admin_page_hoop`.
+#[handler]
+pub async fn detail_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let user_id = req.param::<String>("user_id").unwrap_or_default();
+    let conn = db::pool();
+    let Some(user) = Users::find_active_by_id(user_id).one(conn).await? else {
+        return Err(StatusError::not_found().brief("User does not exist.").into());
+    };
+    let events = AuditEvents::find()
+        .filter(audit_events::Column::TargetType.eq("user"))
+        .filter(audit_events::Column::TargetId.eq(&user.id))
+        .order_by_desc(audit_events::Column::CreatedAt)
+        .order_by_desc(audit_events::Column::Id)
+        .limit(DETAIL_AUDIT_EVENTS)
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|event| AuditRow {
+            created_at: page_time(Some(event.created_at)),
+            action: event.action,
+            actor_id: event.actor_id.unwrap_or_else(|| "system".into()),
+            diff: event.diff.map(|diff| diff.to_string()).unwrap_or_default(),
+        })
+        .collect();
+    let tmpl = UserDetailPageTemplate {
+        vip_start_time: page_time(user.vip_start_time),
+        vip_end_time: page_time(user.vip_end_time),
+        created_at: page_time(Some(user.created_at)),
+        user_json: serde_json::to_string(&SafeUser::from(user.clone())).map_err(anyhow::Error::from)?,
+        user: user.into(),
+        events,
+        tr: Tr(Locale::of(req)),
+    };
+    res.render(Text::Html(tmpl.render().unwrap()));
+    Ok(())
+}
+
 #[derive(Deserialize, Debug, Validate, ToSchema, Default)]
 pub struct CreateInData {
     #[validate(email(message = "Please enter a valid email address"))]
@@ -49,36 +120,71 @@ pub struct CreateInData {
     pub password: String,
 }
 #[endpoint(tags("users"))]
-pub async fn create_user(idata: JsonBody<CreateInData>) -> JsonResult<SafeUser> {
-    let CreateInData { email, password } = idata.into_inner();
-    let id = Ulid::new().to_string();
+pub async fn create_user(
+    idata: JsonBody<CreateInData>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<SafeUser> {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let CreateInData { email, password } = idata;
     let password = utils::hash_password(&password)?;
-    let conn = db::pool();
-    let now = time::OffsetDateTime::now_utc();
-    let now_primitive = time::PrimitiveDateTime::new(now.date(), now.time());
+    let user = insert_user(email, Some(password), None).await?;
+    account::send_verification_email(&user).await?;
+    let user = SafeUser::from(user);
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_USER_CREATE,
+            actor_id: Some(&user.id),
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            diff: Some(audit::diff(None, Some(&user))),
+        },
+    )
+    .await;
+
+    json_ok(user)
+}
+
+/// Inserts a regular, non-VIP account with every column set to its initial value.
+/// Without `password_hash` the account gets an unusable random password.
+pub async fn insert_user(
+    email: String,
+    password_hash: Option<String>,
+    email_verified_at: Option<time::PrimitiveDateTime>,
+) -> AppResult<users::Model> {
+    let now = utils::now_primitive();
+    let password_set = password_hash.is_some();
+    let password_hash = match password_hash {
+        Some(hash) => hash,
+        None => utils::hash_password(&utils::random_string(32))?,
+    };
     let model = users::ActiveModel {
-        id: Set(id.clone()),
-        email: Set(email.clone()),
-        password: Set(password.clone()),
+        id: Set(Ulid::new().to_string()),
+        email: Set(email),
+        password: Set(password_hash),
+        password_set: Set(password_set),
         is_vip: Set(false),
         vip_start_time: Set(None),
         vip_end_time: Set(None),
         vip_level: Set(0),
-        created_at: Set(now_primitive),
-        updated_at: Set(now_primitive),
+        created_at: Set(now),
+        updated_at: Set(now),
+        failed_login_count: Set(0),
+        locked_until: Set(None),
+        totp_secret: Set(None),
+        totp_enabled: Set(false),
+        totp_last_step: Set(None),
+        email_verified_at: Set(email_verified_at),
+        is_admin: Set(false),
+        deleted_at: Set(None),
+        deletion_scheduled_at: Set(None),
+        anonymized_at: Set(None),
+        locale: Set(None),
     };
-    Users::insert(model).exec(conn).await?;
-
-    json_ok(SafeUser {
-        id,
-        email,
-        is_vip: false,
-        vip_start_time: None,
-        vip_end_time: None,
-        vip_level: 0,
-        created_at: now_primitive,
-        updated_at: now_primitive,
-    })
+    Ok(Users::insert(model).exec_with_returning(db::pool()).await?)
 }
 
 #[derive(Deserialize, Debug, Validate, ToSchema)]
@@ -88,105 +194,364 @@ struct UpdateInData {
     #[validate(length(min = 6, message = "password length must be greater than 5"))]
     password: String,
 }
-#[endpoint(tags("users"), parameters(("user_id", description = "user id")))]
+#[endpoint(
+    tags("users"),
+    parameters(("user_id", description = "user id")),
+    security(("bearer" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
 pub async fn update_user(
     user_id: PathParam<String>,
     idata: JsonBody<UpdateInData>,
+    req: &mut Request,
+    depot: &mut Depot,
 ) -> JsonResult<SafeUser> {
     let user_id = user_id.into_inner();
-    let UpdateInData { email, password } = idata.into_inner();
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let UpdateInData { email, password } = idata;
     let conn = db::pool();
 
-    let Some(user) = Users::find_by_id(user_id).one(conn).await? else {
-        return Err(anyhow::anyhow!("User does not exist.").into());
+    let Some(user) = Users::find_active_by_id(user_id).one(conn).await? else {
+        return Err(ErrorCode::UserNotFound.into());
     };
+    let before = SafeUser::from(user.clone());
     let mut user: users::ActiveModel = user.into();
     user.email = Set(email.to_owned());
     user.password = Set(utils::hash_password(&password)?);
+    user.password_set = Set(true);
 
-    let now = time::OffsetDateTime::now_utc();
-    let now_primitive = time::PrimitiveDateTime::new(now.date(), now.time());
-    user.updated_at = Set(now_primitive);
-    let user: users::Model = user.update(conn).await?;
-    json_ok(SafeUser {
-        id: user.id,
-        email: user.email,
-        is_vip: user.is_vip,
-        vip_start_time: user.vip_start_time,
-        vip_end_time: user.vip_end_time,
-        vip_level: user.vip_level,
-        created_at: user.created_at,
-        updated_at: user.updated_at,
-    })
+    user.updated_at = Set(utils::now_primitive());
+    let user = SafeUser::from(user.update(conn).await?);
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_USER_UPDATE,
+            target_type: Some("user"),
+            target_id: Some(&user.id),
+            diff: Some(audit::diff(Some(&before), Some(&user))),
+            ..Default::default()
+        },
+    )
+    .await;
+    json_ok(user)
 }
 
-#[endpoint(tags("users"))]
-pub async fn delete_user(user_id: PathParam<String>) -> EmptyResult {
+/// Marks the user deleted and signs them out everywhere. The account can be
+/// restored until the retention period in `soft_delete` has passed.
+#[endpoint(tags("users"), security(("bearer" = ["users:write"]), ("api_key" = ["users:write"])))]
+pub async fn delete_user(
+    user_id: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> EmptyResult {
     let user_id = user_id.into_inner();
     let conn = db::pool();
-    Users::delete_by_id(user_id).exec(conn).await?;
+    let Some(user) = Users::find_active_by_id(&user_id).one(conn).await? else {
+        return Err(ErrorCode::UserNotFound.into());
+    };
+    let now = utils::now_primitive();
+    let mut model: users::ActiveModel = user.clone().into();
+    model.deleted_at = Set(Some(now));
+    model.updated_at = Set(now);
+    model.update(conn).await?;
+    session::revoke(&user_id, None).await?;
+    api_key::revoke_all(&user_id).await?;
+    audit::record(
+        req,
+        depot,
+        Event {
+            action: audit::ACTION_USER_DELETE,
+            target_type: Some("user"),
+            target_id: Some(&user_id),
+            diff: Some(audit::diff(Some(&SafeUser::from(user)), None)),
+            ..Default::default()
+        },
+    )
+    .await;
     empty_ok()
 }
 
-#[derive(Debug, Deserialize, Validate, Extractible, ToSchema)]
-#[salvo(extract(default_source(from = "query")))]
-pub struct UserListQuery {
+/// Filters shared by the user list and the bulk export. All given filters must
+/// match; date bounds are ISO 8601 and `*_from` is inclusive, `*_to` exclusive.
+#[derive(Debug, Deserialize, Validate, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct UserFilter {
+    /// Substring of the email address.
     pub email: Option<String>,
+    pub is_vip: Option<bool>,
+    #[salvo(parameter(minimum = 0))]
+    pub vip_level_min: Option<i32>,
+    #[salvo(parameter(minimum = 0))]
+    pub vip_level_max: Option<i32>,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    #[salvo(parameter(value_type = Option<String>, format = DateTime, example = "2026-01-01T00:00:00Z"))]
+    pub created_from: Option<time::PrimitiveDateTime>,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    #[salvo(parameter(value_type = Option<String>, format = DateTime))]
+    pub created_to: Option<time::PrimitiveDateTime>,
+    /// Lower bound on `vip_end_time`, e.g. now to find VIPs that haven't expired.
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    #[salvo(parameter(value_type = Option<String>, format = DateTime))]
+    pub vip_end_from: Option<time::PrimitiveDateTime>,
+    #[serde(default, deserialize_with = "crate::models::deserialize_optional_primitive_datetime")]
+    #[salvo(parameter(value_type = Option<String>, format = DateTime))]
+    pub vip_end_to: Option<time::PrimitiveDateTime>,
+}
+
+impl UserFilter {
+    /// Narrows `select` to the users matching every given filter.
+    pub fn apply(&self, mut select: Select<Users>) -> Select<Users> {
+        if let Some(email) = self.email.as_ref() {
+            select = select.filter(users::Column::Email.contains(email));
+        }
+        if let Some(is_vip) = self.is_vip {
+            select = select.filter(users::Column::IsVip.eq(is_vip));
+        }
+        if let Some(min) = self.vip_level_min {
+            select = select.filter(users::Column::VipLevel.gte(min));
+        }
+        if let Some(max) = self.vip_level_max {
+            select = select.filter(users::Column::VipLevel.lte(max));
+        }
+        if let Some(from) = self.created_from {
+            select = select.filter(users::Column::CreatedAt.gte(from));
+        }
+        if let Some(to) = self.created_to {
+            select = select.filter(users::Column::CreatedAt.lt(to));
+        }
+        if let Some(from) = self.vip_end_from {
+            select = select.filter(users::Column::VipEndTime.gte(from));
+        }
+        if let Some(to) = self.vip_end_to {
+            select = select.filter(users::Column::VipEndTime.lt(to));
+        }
+        select
+    }
+}
+
+#[derive(Debug, Deserialize, Validate, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct UserListQuery {
+    /// Ignored when `cursor` is given.
     #[serde(default = "default_page")]
+    #[validate(range(min = 1, max = 1000000, message = "current_page must be between 1 and 1000000"))]
+    #[salvo(parameter(minimum = 1, maximum = 1000000, default = 1))]
     pub current_page: u64,
     #[serde(default = "default_page_size")]
+    #[validate(range(min = 1, max = 100, message = "page_size must be between 1 and 100"))]
+    #[salvo(parameter(minimum = 1, maximum = 100, default = 10))]
     pub page_size: u64,
+    /// Comma separated fields, each optionally prefixed with `-` for descending
+    /// order. Sortable: `created_at`, `updated_at`, `email`, `vip_level`.
+    #[serde(default = "default_sort")]
+    #[salvo(parameter(
+        default = "created_at",
+        pattern = r"^-?(created_at|updated_at|email|vip_level)(,-?(created_at|updated_at|email|vip_level))*$",
+        example = "-vip_level,email"
+    ))]
+    pub sort: String,
+    /// `next_cursor` of the previous page, to continue after it. Faster than
+    /// `current_page` for deep pages; needs the same `sort`.
+    pub cursor: Option<String>,
 }
 
 fn default_page() -> u64 { 1 }
 fn default_page_size() -> u64 { 10 }
+fn default_sort() -> String { "created_at".into() }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct UserListResponse {
-    pub data: Vec<SafeUser>,
-    pub total: u64,
-    pub current_page: u64,
-    pub page_size: u64,
+const SORTABLE: &[(&str, users::Column)] = &[
+    ("created_at", users::Column::CreatedAt),
+    ("updated_at", users::Column::UpdatedAt),
+    ("email", users::Column::Email),
+    ("vip_level", users::Column::VipLevel),
+];
+
+struct SortKey {
+    field: &'static str,
+    column: users::Column,
+    desc: bool,
 }
 
-#[endpoint(tags("users"))]
-pub async fn list_users(query: &mut Request) -> JsonResult<UserListResponse> {
-    let query: UserListQuery = query.extract().await?;
-    let conn = db::pool();
-    
-    let mut select = Users::find();
-    
-    // Apply email filter if provided
-    if let Some(email) = query.email.as_ref() {
-        select = select.filter(users::Column::Email.contains(email));
+impl SortKey {
+    fn value(&self, user: &users::Model) -> serde_json::Value {
+        let iso = |dt: time::PrimitiveDateTime| {
+            dt.assume_utc()
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default()
+        };
+        match self.field {
+            "created_at" => iso(user.created_at).into(),
+            "updated_at" => iso(user.updated_at).into(),
+            "email" => user.email.clone().into(),
+            _ => user.vip_level.into(),
+        }
+    }
+
+    fn parse_value(&self, value: &serde_json::Value) -> Option<sea_orm::Value> {
+        match self.field {
+            "created_at" | "updated_at" => {
+                let dt = time::OffsetDateTime::parse(
+                    value.as_str()?,
+                    &time::format_description::well_known::Rfc3339,
+                )
+                .ok()?;
+                Some(time::PrimitiveDateTime::new(dt.date(), dt.time()).into())
+            }
+            "email" => Some(value.as_str()?.to_owned().into()),
+            _ => Some(i32::try_from(value.as_i64()?).ok()?.into()),
+        }
+    }
+}
+
+fn parse_sort(sort: &str) -> AppResult<Vec<SortKey>> {
+    let mut keys: Vec<SortKey> = Vec::new();
+    for part in sort.split(',').map(str::trim) {
+        let (name, desc) = match part.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (part, false),
+        };
+        let Some((field, column)) = SORTABLE.iter().find(|(field, _)| *field == name) else {
+            return Err(AppError::public(Message::new("Cannot sort by {name}.").arg("name", name)));
+        };
+        if keys.iter().any(|key| key.field == *field) {
+            return Err(AppError::public(Message::new("{name} is listed twice in sort.").arg("name", name)));
+        }
+        keys.push(SortKey { field, column: *column, desc });
+    }
+    Ok(keys)
+}
+
+/// Position after the last row of a page: its sort values and id, tied to the
+/// sort they were taken for.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    values: Vec<serde_json::Value>,
+    id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()
     }
-    
-    // Get total count
+}
+
+/// Rows strictly after `cursor` in the order of `keys`, with the id as the
+/// final tie breaker.
+fn after_cursor(keys: &[SortKey], cursor: &Cursor) -> Option<Condition> {
+    let values = keys
+        .iter()
+        .zip(&cursor.values)
+        .map(|(key, value)| key.parse_value(value))
+        .collect::<Option<Vec<_>>>()?;
+    if values.len() != keys.len() {
+        return None;
+    }
+    let mut after = Condition::any();
+    for i in 0..=keys.len() {
+        let mut branch = Condition::all();
+        for (key, value) in keys.iter().zip(&values).take(i) {
+            branch = branch.add(key.column.eq(value.clone()));
+        }
+        branch = match keys.get(i) {
+            Some(key) if key.desc => branch.add(key.column.lt(values[i].clone())),
+            Some(key) => branch.add(key.column.gt(values[i].clone())),
+            None => branch.add(users::Column::Id.gt(cursor.id.clone())),
+        };
+        after = after.add(branch);
+    }
+    Some(after)
+}
+
+/// Lists users matching the filters, by offset pages or, for deep pages, by cursor.
+#[endpoint(tags("users"), security(("bearer" = ["users:read"]), ("api_key" = ["users:read"])))]
+pub async fn list_users(filter: UserFilter, query: UserListQuery) -> JsonResult<Paginated<SafeUser>> {
+    filter.validate()?;
+    query.validate()?;
+    let keys = parse_sort(&query.sort)?;
+    let conn = db::pool();
+
+    let select = filter.apply(Users::find_active());
     let total = select.clone().count(conn).await?;
-    
-    // Apply pagination
-    let users = select
-        .offset(((query.current_page - 1) * query.page_size) as u64)
-        .limit(query.page_size)
-        .all(conn)
-        .await?
-        .into_iter()
-        .map(|user| SafeUser {
-            id: user.id,
-            email: user.email,
-            is_vip: user.is_vip,
-            vip_start_time: user.vip_start_time,
-            vip_end_time: user.vip_end_time,
-            vip_level: user.vip_level,
-            created_at: user.created_at,
-            updated_at: user.updated_at,
+
+    let mut select = select;
+    for key in &keys {
+        select = if key.desc {
+            select.order_by_desc(key.column)
+        } else {
+            select.order_by_asc(key.column)
+        };
+    }
+    select = select.order_by_asc(users::Column::Id);
+    select = match query.cursor.as_deref() {
+        Some(cursor) => {
+            let condition = Cursor::decode(cursor)
+                .filter(|cursor| cursor.sort == query.sort)
+                .and_then(|cursor| after_cursor(&keys, &cursor))
+                .ok_or_else(|| AppError::public("The cursor is invalid for this query."))?;
+            select.filter(condition)
+        }
+        None => select.offset((query.current_page - 1) * query.page_size),
+    };
+    // One extra row tells whether there is a next page.
+    let mut users = select.limit(query.page_size + 1).all(conn).await?;
+    let next_cursor = if users.len() as u64 > query.page_size {
+        users.truncate(query.page_size as usize);
+        users.last().map(|last| {
+            Cursor {
+                sort: query.sort.clone(),
+                values: keys.iter().map(|key| key.value(last)).collect(),
+                id: last.id.clone(),
+            }
+            .encode()
         })
-        .collect::<Vec<_>>();
-    
-    json_ok(UserListResponse {
-        data: users,
+    } else {
+        None
+    };
+
+    json_ok(Paginated {
+        data: users.into_iter().map(SafeUser::from).collect(),
         total,
         current_page: query.current_page,
         page_size: query.page_size,
+        next_cursor,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_and_cursor() {
+        let keys = parse_sort("-vip_level,email").unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0].desc && !keys[1].desc);
+        assert!(parse_sort("password").is_err());
+        assert!(parse_sort("email,-email").is_err());
+
+        let cursor = Cursor {
+            sort: "-vip_level,email".into(),
+            values: vec![3.into(), "a@example.com".into()],
+            id: "01J0000000000000000000000".into(),
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert!(after_cursor(&keys, &decoded).is_some());
+        assert!(after_cursor(&keys[..1], &Cursor { values: vec!["x".into()], ..decoded }).is_none());
+        assert!(Cursor::decode("not a cursor").is_none());
+    }
+
+    #[test]
+    fn page_number_is_bounded() {
+        let query = |current_page: u64| -> UserListQuery {
+            serde_json::from_value(serde_json::json!({ "current_page": current_page })).unwrap()
+        };
+        assert!(query(1).validate().is_ok());
+        assert!(query(u64::MAX).validate().is_err());
+    }
+}
@@ -0,0 +1,10 @@
+use salvo::prelude::*;
+
+use crate::jwt_keys;
+
+/// Public keys for services that verify our tokens without being able to mint them.
+#[handler]
+pub async fn jwks(res: &mut Response) {
+    res.add_header("cache-control", "public, max-age=300", true).ok();
+    res.render(Json(jwt_keys::get().jwks()));
+}
@@ -0,0 +1,46 @@
+//! Permission names carried by tokens and API keys.
+//!
+//! Endpoints declare what they need with
+//! `#[endpoint(security(("bearer" = ["users:read"]), ("api_key" = ["users:read"])))]`;
+//! `scope_hoop` enforces it from the generated OpenAPI document.
+
+use crate::entities::users;
+
+pub const USERS_READ: &str = "users:read";
+pub const USERS_WRITE: &str = "users:write";
+pub const VIP_GRANT: &str = "vip:grant";
+pub const USERS_IMPERSONATE: &str = "users:impersonate";
+pub const USERS_RESTORE: &str = "users:restore";
+pub const USERS_IMPORT: &str = "users:import";
+pub const USERS_EXPORT: &str = "users:export";
+pub const SESSIONS_MANAGE: &str = "sessions:manage";
+pub const AUDIT_READ: &str = "audit:read";
+pub const STATS_READ: &str = "stats:read";
+
+pub const ALL: &[&str] = &[
+    USERS_READ,
+    USERS_WRITE,
+    VIP_GRANT,
+    USERS_IMPERSONATE,
+    USERS_RESTORE,
+    USERS_IMPORT,
+    USERS_EXPORT,
+    SESSIONS_MANAGE,
+    AUDIT_READ,
+    STATS_READ,
+];
+
+/// Granted to every signed-in user; also assumed for tokens issued before
+/// tokens carried scopes. Empty, because the `users:*` endpoints act on any
+/// account: regular users manage themselves under `/api/me`.
+pub const USER_DEFAULT: &[&str] = &[];
+
+pub fn is_known(scope: &str) -> bool {
+    ALL.contains(&scope)
+}
+
+/// Scopes of a session token for `user`.
+pub fn for_user(user: &users::Model) -> Vec<String> {
+    let scopes: &[&str] = if user.is_admin { ALL } else { USER_DEFAULT };
+    scopes.iter().map(|scope| scope.to_string()).collect()
+}
@@ -0,0 +1,169 @@
+use std::time::SystemTime;
+
+use opentelemetry::global;
+use opentelemetry::trace::{Span as _, SpanKind, Status, TraceContextExt, Tracer as _, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider, Tracer};
+use salvo::http::HeaderMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::TelemetryConfig;
+
+const TRACER_NAME: &str = "ttbox_salvo";
+
+/// Build the OTLP tracer provider and install it globally together with the W3C
+/// `traceparent` propagator.
+///
+/// Returns `None` when telemetry is disabled; the caller should hold the provider
+/// and call [`SdkTracerProvider::shutdown`] before exiting so buffered spans get flushed.
+pub fn init(config: &TelemetryConfig) -> Option<SdkTracerProvider> {
+    if !config.enabled {
+        return None;
+    }
+    let provider = match build_provider(config) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("Failed to set up the OTLP exporter: {e}");
+            std::process::exit(1);
+        }
+    };
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+    Some(provider)
+}
+
+pub fn build_provider(config: &TelemetryConfig) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.otlp_endpoint)
+        .build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build())
+}
+
+pub fn tracer(provider: &SdkTracerProvider) -> Tracer {
+    provider.tracer(TRACER_NAME)
+}
+
+/// Read the remote parent from an incoming `traceparent` header.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Write the `traceparent` header of `span` into outgoing headers.
+pub fn inject_context(span: &tracing::Span, headers: &mut HeaderMap) {
+    let cx = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut HeaderInjector(headers))
+    });
+}
+
+/// Trace id of the current span, if it is being exported.
+pub fn current_trace_id() -> Option<String> {
+    let cx = tracing::Span::current().context();
+    let span_context = cx.span().span_context().clone();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// SeaORM metric callback recording each executed statement as a client span
+/// under the current request span.
+pub fn record_db_query(info: &sea_orm::metric::Info<'_>) {
+    let parent = tracing::Span::current().context();
+    if !parent.span().span_context().is_valid() {
+        return;
+    }
+    let end = SystemTime::now();
+    let start = end.checked_sub(info.elapsed).unwrap_or(end);
+    let tracer = global::tracer(TRACER_NAME);
+    let mut span = tracer
+        .span_builder("db.query")
+        .with_kind(SpanKind::Client)
+        .with_start_time(start)
+        .with_attributes([
+            KeyValue::new("db.system", "mysql"),
+            KeyValue::new("db.statement", info.statement.sql.clone()),
+        ])
+        .start_with_context(&tracer, &parent);
+    if info.failed {
+        span.set_status(Status::error("query failed"));
+    }
+    span.end_with_timestamp(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use opentelemetry::trace::{Tracer as _, TracerProvider as _};
+
+    use super::*;
+
+    /// Accepts one OTLP/HTTP export and reports its request line.
+    fn collector_stand_in() -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let mut stream = stream;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            tx.send(request_line.trim().to_owned()).unwrap();
+        });
+        (endpoint, rx)
+    }
+
+    #[test]
+    fn test_spans_are_exported_over_otlp_http() {
+        let (endpoint, rx) = collector_stand_in();
+        let provider = build_provider(&TelemetryConfig {
+            enabled: true,
+            otlp_endpoint: endpoint,
+            ..Default::default()
+        })
+        .unwrap();
+
+        provider.tracer("test").in_span("exported", |_| {});
+        provider.force_flush().unwrap();
+
+        let request_line = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(request_line, "POST /v1/traces HTTP/1.1");
+        provider.shutdown().unwrap();
+    }
+}
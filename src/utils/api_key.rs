@@ -0,0 +1,60 @@
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use super::one_time_token::hash_token;
+use crate::entities::{api_keys, prelude::ApiKeys};
+use crate::{db, utils, AppResult};
+
+/// Every key starts with this, which is how the auth hoop tells keys from JWTs.
+pub const KEY_PREFIX: &str = "ttb_";
+
+/// `last_used_at` is refreshed at most this often, so busy keys don't write on every request.
+const LAST_USED_GRANULARITY_SECONDS: i64 = 60;
+
+/// Returns `(prefix, key)`. The prefix is stored in clear to find the key and to
+/// show it in listings; the whole key is stored only as a hash.
+pub fn generate() -> (String, String) {
+    let prefix = format!("{KEY_PREFIX}{}", utils::random_string(8));
+    let key = format!("{prefix}_{}", utils::random_string(40));
+    (prefix, key)
+}
+
+/// Looks up an active, unexpired key and records that it was used.
+pub async fn authenticate(key: &str) -> AppResult<Option<api_keys::Model>> {
+    let Some((prefix, _)) = key.rsplit_once('_') else {
+        return Ok(None);
+    };
+    let conn = db::pool();
+    let now = utils::now_primitive();
+    let Some(row) = ApiKeys::find()
+        .filter(api_keys::Column::Prefix.eq(prefix))
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .one(conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    if row.secret_hash != hash_token(key) || row.expires_at.is_some_and(|at| at <= now) {
+        return Ok(None);
+    }
+    let stale_before = now - time::Duration::seconds(LAST_USED_GRANULARITY_SECONDS);
+    if row.last_used_at.is_none_or(|at| at < stale_before) {
+        ApiKeys::update_many()
+            .col_expr(api_keys::Column::LastUsedAt, Expr::value(now))
+            .filter(api_keys::Column::Id.eq(&row.id))
+            .exec(conn)
+            .await?;
+    }
+    Ok(Some(row))
+}
+
+/// Revokes every key of `user_id`, e.g. when the account is deleted.
+pub async fn revoke_all(user_id: &str) -> AppResult<u64> {
+    let result = ApiKeys::update_many()
+        .col_expr(api_keys::Column::RevokedAt, Expr::value(utils::now_primitive()))
+        .filter(api_keys::Column::UserId.eq(user_id))
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .exec(db::pool())
+        .await?;
+    Ok(result.rows_affected)
+}
@@ -4,8 +4,12 @@ use argon2::{
 };
 use rand::Rng;
 use std::iter;
+use std::sync::LazyLock;
+
+pub mod api_key;
+pub mod one_time_token;
+pub mod session;
 
-#[allow(dead_code)]
 #[inline]
 pub fn random_string(limit: usize) -> String {
     iter::repeat(())
@@ -16,7 +20,7 @@ pub fn random_string(limit: usize) -> String {
 }
 
 pub fn verify_password(password: &str, password_hash: &str) -> anyhow::Result<()> {
-    let hash = PasswordHash::new(&password_hash)
+    let hash = PasswordHash::new(password_hash)
         .map_err(|e| anyhow::anyhow!("invalid password hash: {}", e))?;
     let result = hash.verify_password(&[&Argon2::default()], password);
     match result {
@@ -25,9 +29,58 @@ pub fn verify_password(password: &str, password_hash: &str) -> anyhow::Result<()
     }
 }
 
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password(&random_string(16)).expect("dummy password hash should be generated")
+});
+
+/// Builds the hash behind [`verify_dummy_password`] at startup, so the first
+/// login for an unknown email doesn't pay for hashing too.
+pub fn init_dummy_password() {
+    LazyLock::force(&DUMMY_HASH);
+}
+
+/// Runs a password verification against a throwaway hash, so that a login for an
+/// unknown email takes as long as one with a wrong password.
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(password, &DUMMY_HASH);
+}
+
+/// Compares secrets without returning early at the first differing byte.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Current UTC time in the naive form stored by the entities.
+pub fn now_primitive() -> time::PrimitiveDateTime {
+    let now = time::OffsetDateTime::now_utc();
+    time::PrimitiveDateTime::new(now.date(), now.time())
+}
+
+/// IP address of the connected peer.
+pub fn client_ip(req: &salvo::Request) -> Option<String> {
+    req.remote_addr()
+        .clone()
+        .into_std()
+        .map(|addr| addr.ip().to_string())
+}
+
 pub fn hash_password(password: &str) -> anyhow::Result<String> {
     let salt = SaltString::generate(&mut OsRng);
     Ok(PasswordHash::generate(Argon2::default(), password, &salt)
         .map_err(|e| anyhow::anyhow!("failed to generate password hash: {}", e))?
         .to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_compares_whole_strings() {
+        assert!(constant_time_eq("token", "token"));
+        assert!(constant_time_eq("", ""));
+        assert!(!constant_time_eq("token", "tokeN"));
+        assert!(!constant_time_eq("token", "token1"));
+        assert!(!constant_time_eq("token", ""));
+    }
+}
@@ -0,0 +1,69 @@
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, Set};
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+
+use crate::entities::{prelude::UserTokens, user_tokens};
+use crate::{db, utils, AppResult};
+
+pub const PURPOSE_VERIFY_EMAIL: &str = "verify_email";
+pub const PURPOSE_PASSWORD_RESET: &str = "password_reset";
+pub const PURPOSE_MAGIC_LINK: &str = "magic_link";
+pub const PURPOSE_MFA_PENDING: &str = "mfa_pending";
+
+/// Tokens are long random strings, so a plain SHA-256 is enough to keep the
+/// stored value useless while still allowing lookup by hash.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Creates a single-use token for `purpose`, dropping any unused one issued before.
+pub async fn issue(user_id: &str, purpose: &str, ttl_seconds: i64) -> AppResult<String> {
+    let conn = db::pool();
+    UserTokens::delete_many()
+        .filter(user_tokens::Column::UserId.eq(user_id))
+        .filter(user_tokens::Column::Purpose.eq(purpose))
+        .filter(user_tokens::Column::UsedAt.is_null())
+        .exec(conn)
+        .await?;
+
+    let token = utils::random_string(40);
+    let now = utils::now_primitive();
+    let model = user_tokens::ActiveModel {
+        id: Set(Ulid::new().to_string()),
+        user_id: Set(user_id.to_owned()),
+        purpose: Set(purpose.to_owned()),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(now + time::Duration::seconds(ttl_seconds)),
+        used_at: Set(None),
+        created_at: Set(now),
+    };
+    UserTokens::insert(model).exec(conn).await?;
+    Ok(token)
+}
+
+/// Marks a valid token as used and returns the user it was issued to.
+///
+/// Returns `None` for unknown, expired or already used tokens.
+pub async fn consume(token: &str, purpose: &str) -> AppResult<Option<String>> {
+    let conn = db::pool();
+    let now = utils::now_primitive();
+    let Some(row) = UserTokens::find()
+        .filter(user_tokens::Column::TokenHash.eq(hash_token(token)))
+        .filter(user_tokens::Column::Purpose.eq(purpose))
+        .filter(user_tokens::Column::UsedAt.is_null())
+        .filter(user_tokens::Column::ExpiresAt.gt(now))
+        .one(conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    // Guarded on `used_at` so two concurrent requests can't both redeem it.
+    let result = UserTokens::update_many()
+        .col_expr(user_tokens::Column::UsedAt, Expr::value(now))
+        .filter(user_tokens::Column::Id.eq(&row.id))
+        .filter(user_tokens::Column::UsedAt.is_null())
+        .exec(conn)
+        .await?;
+    Ok((result.rows_affected == 1).then_some(row.user_id))
+}
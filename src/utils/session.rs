@@ -0,0 +1,126 @@
+use salvo::http::header::USER_AGENT;
+use salvo::Request;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, Set};
+use ulid::Ulid;
+
+use crate::entities::{prelude::Sessions, sessions};
+use crate::{db, utils, AppResult};
+
+/// Optional header the desktop app uses to name the machine it runs on.
+pub const DEVICE_NAME_HEADER: &str = "x-device-name";
+
+/// `last_used_at` is refreshed at most this often, so each request doesn't write.
+const LAST_USED_GRANULARITY_SECONDS: i64 = 60;
+
+/// Records a login from `req` and returns the session id to put in the token.
+pub async fn create(user_id: &str, req: &Request, ttl_seconds: i64) -> AppResult<String> {
+    let now = utils::now_primitive();
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|ua| ua.chars().take(512).collect::<String>());
+    let device = req
+        .headers()
+        .get(DEVICE_NAME_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|name| name.chars().take(128).collect())
+        .unwrap_or_else(|| describe_device(user_agent.as_deref().unwrap_or_default()));
+    let id = Ulid::new().to_string();
+    let model = sessions::ActiveModel {
+        id: Set(id.clone()),
+        user_id: Set(user_id.to_owned()),
+        ip: Set(utils::client_ip(req)),
+        user_agent: Set(user_agent),
+        device: Set(device),
+        created_at: Set(now),
+        last_used_at: Set(now),
+        expires_at: Set(now + time::Duration::seconds(ttl_seconds)),
+        revoked_at: Set(None),
+    };
+    Sessions::insert(model).exec(db::pool()).await?;
+    Ok(id)
+}
+
+/// Whether the session behind a token is still valid; records that it was used.
+pub async fn check_and_touch(session_id: &str) -> AppResult<bool> {
+    let conn = db::pool();
+    let now = utils::now_primitive();
+    let Some(session) = Sessions::find_by_id(session_id).one(conn).await? else {
+        return Ok(false);
+    };
+    if session.revoked_at.is_some() || session.expires_at <= now {
+        return Ok(false);
+    }
+    if session.last_used_at < now - time::Duration::seconds(LAST_USED_GRANULARITY_SECONDS) {
+        Sessions::update_many()
+            .col_expr(sessions::Column::LastUsedAt, Expr::value(now))
+            .filter(sessions::Column::Id.eq(session_id))
+            .exec(conn)
+            .await?;
+    }
+    Ok(true)
+}
+
+/// Revokes one session of `user_id`, or all of them when `session_id` is `None`.
+/// Returns how many sessions were revoked.
+pub async fn revoke(user_id: &str, session_id: Option<&str>) -> AppResult<u64> {
+    let mut update = Sessions::update_many()
+        .col_expr(sessions::Column::RevokedAt, Expr::value(utils::now_primitive()))
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::RevokedAt.is_null());
+    if let Some(session_id) = session_id {
+        update = update.filter(sessions::Column::Id.eq(session_id));
+    }
+    Ok(update.exec(db::pool()).await?.rows_affected)
+}
+
+/// A short human label such as "Chrome on Windows" for clients that don't name themselves.
+pub fn describe_device(user_agent: &str) -> String {
+    const PLATFORMS: &[(&str, &str)] = &[
+        ("Windows", "Windows"),
+        ("iPhone", "iPhone"),
+        ("iPad", "iPad"),
+        ("Android", "Android"),
+        ("Mac OS", "macOS"),
+        ("Linux", "Linux"),
+    ];
+    const BROWSERS: &[(&str, &str)] = &[
+        ("Edg/", "Edge"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ];
+    let find = |table: &[(&str, &'static str)]| {
+        table
+            .iter()
+            .find(|(needle, _)| user_agent.contains(needle))
+            .map(|(_, label)| *label)
+    };
+    match (find(BROWSERS), find(PLATFORMS)) {
+        (Some(browser), Some(platform)) => format!("{browser} on {platform}"),
+        (Some(label), None) | (None, Some(label)) => label.to_owned(),
+        (None, None) => "Unknown device".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_browser_and_platform() {
+        let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36";
+        assert_eq!(describe_device(chrome), "Chrome on Windows");
+        let edge = "Mozilla/5.0 (Windows NT 10.0) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36 Edg/126.0";
+        assert_eq!(describe_device(edge), "Edge on Windows");
+        let safari = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1";
+        assert_eq!(describe_device(safari), "Safari on iPhone");
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0";
+        assert_eq!(describe_device(firefox), "Firefox on Linux");
+        assert_eq!(describe_device("curl/8.7.1"), "curl");
+        assert_eq!(describe_device(""), "Unknown device");
+    }
+}